@@ -0,0 +1,227 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long a cached answer about branches stays fresh before it is
+/// revalidated. What was read at a commit never changes, so that is kept for good.
+pub const REF_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Responses not fetched or revalidated for this long are dropped when the
+/// cache is saved. Their URLs name branch heads and pages that move on, so
+/// most are never asked for again.
+const RESPONSE_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+#[derive(Default, Serialize, Deserialize)]
+struct Data {
+    /// Package versions by commit SHA, then by package path. `None` when the
+    /// package isn't there or its version couldn't be read.
+    #[serde(default)]
+    versions: BTreeMap<String, BTreeMap<String, Option<String>>>,
+    /// Terraform providers packaged at each commit SHA. `None` when the
+    /// commit has no `providers.json`.
+    #[serde(default)]
+    providers: BTreeMap<String, Option<Vec<Provider>>>,
+    /// GitHub API responses by URL.
+    #[serde(default)]
+    responses: BTreeMap<String, Response>,
+}
+
+/// A Terraform provider packaged in nixpkgs, with its version as written.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Provider {
+    pub attr: String,
+    pub source: String,
+    pub version: String,
+}
+
+/// What a GitHub API request returned, in the form it is used in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Response {
+    pub etag: Option<String>,
+    /// When it was fetched or last revalidated, in seconds since the Unix epoch.
+    pub fetched: u64,
+    pub value: serde_json::Value,
+}
+
+impl Response {
+    pub fn is_fresh(&self, now: u64) -> bool {
+        now.saturating_sub(self.fetched) < REF_TTL.as_secs()
+    }
+}
+
+/// Lookups against nixpkgs, kept on disk between runs. Changes are written
+/// back when the cache is dropped. It can be shared between threads.
+pub struct Cache {
+    path: PathBuf,
+    data: Mutex<Data>,
+    dirty: AtomicBool,
+}
+
+impl Cache {
+    /// `$XDG_CACHE_HOME/tfg/nixpkgs.json`, falling back to `~/.cache`.
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = match std::env::var_os("XDG_CACHE_HOME").filter(|d| !d.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+        };
+        Some(dir.join("tfg").join("nixpkgs.json"))
+    }
+
+    /// Load the cache at `path`. A missing or unreadable cache starts out empty.
+    pub fn open(path: &Path) -> Cache {
+        Cache {
+            path: path.to_path_buf(),
+            data: Mutex::new(load(path).unwrap_or_default()),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// The version of the package at `path` at commit `sha`, if it was looked up before.
+    pub fn version(&self, sha: &str, path: &str) -> Option<Option<String>> {
+        self.data.lock().unwrap().versions.get(sha)?.get(path).cloned()
+    }
+
+    pub fn set_version(&self, sha: &str, path: &str, version: Option<String>) {
+        let mut data = self.data.lock().unwrap();
+        data.versions
+            .entry(sha.to_string())
+            .or_default()
+            .insert(path.to_string(), version);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// The providers packaged at commit `sha`, if they were looked up before.
+    pub fn providers(&self, sha: &str) -> Option<Option<Vec<Provider>>> {
+        self.data.lock().unwrap().providers.get(sha).cloned()
+    }
+
+    pub fn set_providers(&self, sha: &str, providers: Option<Vec<Provider>>) {
+        self.data.lock().unwrap().providers.insert(sha.to_string(), providers);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    pub fn response(&self, url: &str) -> Option<Response> {
+        self.data.lock().unwrap().responses.get(url).cloned()
+    }
+
+    pub fn set_response(&self, url: &str, response: Response) {
+        self.data.lock().unwrap().responses.insert(url.to_string(), response);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Write the cache back, merged with whatever other runs wrote meanwhile,
+    /// leaving out responses older than `RESPONSE_MAX_AGE`.
+    pub fn save(&self) -> Result<()> {
+        let mut merged = load(&self.path).unwrap_or_default();
+        let data = self.data.lock().unwrap();
+        for (sha, versions) in &data.versions {
+            merged.versions.entry(sha.clone()).or_default().extend(versions.clone());
+        }
+        merged.providers.extend(data.providers.clone());
+        for (url, response) in &data.responses {
+            let newer = match merged.responses.get(url) {
+                Some(theirs) => theirs.fetched <= response.fetched,
+                None => true,
+            };
+            if newer {
+                merged.responses.insert(url.clone(), response.clone());
+            }
+        }
+        let now = now();
+        merged
+            .responses
+            .retain(|_, r| now.saturating_sub(r.fetched) < RESPONSE_MAX_AGE.as_secs());
+
+        let dir = self.path.parent().context("invalid cache path")?;
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        // Rename into place so concurrent runs never read a partial file
+        let tmp = self.path.with_extension(format!("json.{}", std::process::id()));
+        std::fs::write(&tmp, serde_json::to_vec(&merged)?)
+            .with_context(|| format!("failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("failed to write {}", self.path.display()))?;
+        self.dirty.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Drop for Cache {
+    fn drop(&mut self) {
+        if self.dirty.load(Ordering::Relaxed) {
+            if let Err(e) = self.save() {
+                eprintln!("Warning: could not save the nixpkgs cache: {:#}", e);
+            }
+        }
+    }
+}
+
+fn load(path: &Path) -> Option<Data> {
+    let content = std::fs::read(path).ok()?;
+    serde_json::from_slice(&content).ok()
+}
+
+/// Seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn test_round_trip_and_merge() {
+        let dir = TestDir::new("cache");
+        let path = dir.join("tfg").join("nixpkgs.json");
+        let response = |fetched: u64, value: &str| Response {
+            etag: Some(format!("\"{}\"", value)),
+            fetched,
+            value: serde_json::json!(value),
+        };
+
+        let first = Cache::open(&path);
+        first.set_version("abc", "terraform.nix", Some("1.5.7".to_string()));
+        let aws = Provider {
+            attr: "aws".to_string(),
+            source: "hashicorp/aws".to_string(),
+            version: "5.31.0".to_string(),
+        };
+        first.set_providers("abc", Some(vec![aws.clone()]));
+        let now = now();
+        first.set_response("https://example.com/refs", response(now - 100, "old"));
+        first.set_response("https://example.com/stale", response(0, "stale"));
+        // Another run writes in the meantime
+        let second = Cache::open(&path);
+        second.set_version("def", "terraform.nix", None);
+        second.set_response("https://example.com/refs", response(now, "new"));
+        drop(second);
+        drop(first);
+
+        let cache = Cache::open(&path);
+        let abc = cache.version("abc", "terraform.nix");
+        let def = cache.version("def", "terraform.nix");
+        let unknown = cache.version("abc", "terragrunt.nix");
+        let providers = cache.providers("abc");
+        let refs = cache.response("https://example.com/refs").unwrap();
+        let stale = cache.response("https://example.com/stale");
+
+        assert_eq!(abc, Some(Some("1.5.7".to_string())));
+        assert_eq!(def, Some(None));
+        assert_eq!(unknown, None);
+        assert_eq!(providers, Some(Some(vec![aws])));
+        // The newer response wins
+        assert_eq!(refs.value, "new");
+        assert!(refs.is_fresh(now + REF_TTL.as_secs() - 1));
+        assert!(!refs.is_fresh(now + REF_TTL.as_secs()));
+        // Responses past their age are dropped
+        assert!(stale.is_none());
+    }
+}
@@ -1,31 +1,141 @@
+use crate::diagnostic::Location;
+use crate::version_set::{Cut, VersionSet};
 use anyhow::{bail, Context, Result};
 use std::cmp::Ordering;
 use std::fmt;
+use std::ops::Range;
 
+/// A single dot-separated pre-release identifier, e.g. `beta1` or `2`.
 #[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Identifier {
+    Numeric(u64),
+    AlphaNumeric(String),
+}
+
+impl Identifier {
+    fn parse(s: &str) -> Result<Self> {
+        if s.is_empty() || !s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            bail!("invalid version identifier: {:?}", s);
+        }
+        if s.chars().all(|c| c.is_ascii_digit()) {
+            Ok(Identifier::Numeric(s.parse().context("numeric identifier out of range")?))
+        } else {
+            Ok(Identifier::AlphaNumeric(s.to_string()))
+        }
+    }
+}
+
+impl Ord for Identifier {
+    /// Numeric identifiers compare numerically and sort before alphanumeric ones,
+    /// which compare lexically.
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Identifier::Numeric(a), Identifier::Numeric(b)) => a.cmp(b),
+            (Identifier::Numeric(_), Identifier::AlphaNumeric(_)) => Ordering::Less,
+            (Identifier::AlphaNumeric(_), Identifier::Numeric(_)) => Ordering::Greater,
+            (Identifier::AlphaNumeric(a), Identifier::AlphaNumeric(b)) => a.cmp(b),
+        }
+    }
+}
+
+impl PartialOrd for Identifier {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Identifier::Numeric(n) => write!(f, "{}", n),
+            Identifier::AlphaNumeric(s) => f.write_str(s),
+        }
+    }
+}
+
+/// A Terraform version: `X.Y.Z` with optional `-pre.release` and `+build` parts.
+///
+/// Ordering follows go-version: a pre-release sorts before its release, and
+/// build metadata is ignored for both ordering and equality.
+#[derive(Debug, Clone)]
 pub struct Version {
     pub major: u64,
     pub minor: u64,
     pub patch: u64,
+    pub pre: Vec<Identifier>,
+    pub build: Vec<String>,
 }
 
 impl Version {
+    pub fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Version {
+            major,
+            minor,
+            patch,
+            pre: Vec::new(),
+            build: Vec::new(),
+        }
+    }
+
     pub fn parse(s: &str) -> Result<Self> {
         let s = s.trim();
-        let parts: Vec<&str> = s.split('.').collect();
-        match parts.len() {
-            2 => Ok(Version {
-                major: parts[0].parse().context("invalid major version")?,
-                minor: parts[1].parse().context("invalid minor version")?,
-                patch: 0,
-            }),
-            3 => Ok(Version {
-                major: parts[0].parse().context("invalid major version")?,
-                minor: parts[1].parse().context("invalid minor version")?,
-                patch: parts[2].parse().context("invalid patch version")?,
-            }),
+        let s = s.strip_prefix('v').unwrap_or(s);
+
+        let (s, build) = match s.split_once('+') {
+            Some((rest, build)) => {
+                let build: Vec<String> = build.split('.').map(str::to_string).collect();
+                if build
+                    .iter()
+                    .any(|b| b.is_empty() || !b.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+                {
+                    bail!("invalid build metadata in version: {}", s);
+                }
+                (rest, build)
+            }
+            None => (s, Vec::new()),
+        };
+        let (core, pre) = match s.split_once('-') {
+            Some((core, pre)) => {
+                let pre = pre
+                    .split('.')
+                    .map(Identifier::parse)
+                    .collect::<Result<Vec<_>>>()
+                    .with_context(|| format!("invalid pre-release in version: {}", s))?;
+                (core, pre)
+            }
+            None => (s, Vec::new()),
+        };
+
+        let parts: Vec<&str> = core.split('.').collect();
+        let (major, minor, patch) = match parts.len() {
+            2 => (
+                parts[0].parse().context("invalid major version")?,
+                parts[1].parse().context("invalid minor version")?,
+                0,
+            ),
+            3 => (
+                parts[0].parse().context("invalid major version")?,
+                parts[1].parse().context("invalid minor version")?,
+                parts[2].parse().context("invalid patch version")?,
+            ),
             _ => bail!("invalid version format: {}", s),
-        }
+        };
+        Ok(Version {
+            major,
+            minor,
+            patch,
+            pre,
+            build,
+        })
+    }
+
+    pub fn is_prerelease(&self) -> bool {
+        !self.pre.is_empty()
+    }
+
+    /// Whether both versions share the same `X.Y.Z`, ignoring pre-release and build.
+    fn same_release(&self, other: &Version) -> bool {
+        (self.major, self.minor, self.patch) == (other.major, other.minor, other.patch)
     }
 }
 
@@ -35,6 +145,12 @@ impl Ord for Version {
             .cmp(&other.major)
             .then(self.minor.cmp(&other.minor))
             .then(self.patch.cmp(&other.patch))
+            .then_with(|| match (self.pre.is_empty(), other.pre.is_empty()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => self.pre.cmp(&other.pre),
+            })
     }
 }
 
@@ -44,13 +160,29 @@ impl PartialOrd for Version {
     }
 }
 
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
 impl fmt::Display for Version {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if !self.pre.is_empty() {
+            let pre: Vec<String> = self.pre.iter().map(|i| i.to_string()).collect();
+            write!(f, "-{}", pre.join("."))?;
+        }
+        if !self.build.is_empty() {
+            write!(f, "+{}", self.build.join("."))?;
+        }
+        Ok(())
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum Comparator {
     Eq(Version),
     Neq(Version),
@@ -59,13 +191,34 @@ enum Comparator {
     Lt(Version),
     Lte(Version),
     /// ~> X.Y.Z means >= X.Y.Z and < X.(Y+1).0
-    PessimisticPatch { major: u64, minor: u64, patch: u64 },
+    PessimisticPatch(Version),
     /// ~> X.Y means >= X.Y.0 and < (X+1).0.0
-    PessimisticMinor { major: u64, minor: u64 },
+    PessimisticMinor(Version),
 }
 
 impl Comparator {
+    /// The version written in the constraint.
+    fn version(&self) -> &Version {
+        match self {
+            Comparator::Eq(v)
+            | Comparator::Neq(v)
+            | Comparator::Gt(v)
+            | Comparator::Gte(v)
+            | Comparator::Lt(v)
+            | Comparator::Lte(v)
+            | Comparator::PessimisticPatch(v)
+            | Comparator::PessimisticMinor(v) => v,
+        }
+    }
+
     fn matches(&self, v: &Version) -> bool {
+        // Like Terraform, a pre-release only matches a comparator that names a
+        // pre-release of the same X.Y.Z; release-only comparators skip them.
+        let req = self.version();
+        if v.is_prerelease() && !(req.is_prerelease() && v.same_release(req)) {
+            return false;
+        }
+
         match self {
             Comparator::Eq(req) => v == req,
             Comparator::Neq(req) => v != req,
@@ -73,96 +226,472 @@ impl Comparator {
             Comparator::Gte(req) => v >= req,
             Comparator::Lt(req) => v < req,
             Comparator::Lte(req) => v <= req,
-            Comparator::PessimisticPatch {
-                major,
-                minor,
-                patch,
-            } => {
-                let lower = Version {
-                    major: *major,
-                    minor: *minor,
-                    patch: *patch,
-                };
-                let upper = Version {
-                    major: *major,
-                    minor: minor + 1,
-                    patch: 0,
-                };
-                v >= &lower && v < &upper
+            Comparator::PessimisticPatch(lower) => {
+                // A pessimistic pre-release constraint only admits pre-releases
+                if lower.is_prerelease() && !v.is_prerelease() {
+                    return false;
+                }
+                let upper = Version::new(lower.major, lower.minor + 1, 0);
+                v >= lower && v < &upper
             }
-            Comparator::PessimisticMinor { major, minor } => {
-                let lower = Version {
-                    major: *major,
-                    minor: *minor,
-                    patch: 0,
-                };
-                let upper = Version {
-                    major: major + 1,
-                    minor: 0,
-                    patch: 0,
-                };
-                v >= &lower && v < &upper
+            Comparator::PessimisticMinor(lower) => {
+                if lower.is_prerelease() && !v.is_prerelease() {
+                    return false;
+                }
+                let upper = Version::new(lower.major + 1, 0, 0);
+                v >= lower && v < &upper
             }
         }
     }
+
+    /// Explain the parts of a comparator that tend to surprise people: what
+    /// `~>` expands to, and the pre-release rule when it rejects `v`.
+    fn note(&self, v: &Version) -> Option<String> {
+        let req = self.version();
+        if v.is_prerelease() && !(req.is_prerelease() && v.same_release(req)) {
+            return Some(format!(
+                "pre-release {} only matches comparators naming a pre-release of {}.{}.{}",
+                v, v.major, v.minor, v.patch
+            ));
+        }
+        match self {
+            Comparator::PessimisticPatch(lower) => Some(format!(
+                "means >= {} and < {}.{}.0: only the patch number may increase",
+                lower,
+                lower.major,
+                lower.minor + 1
+            )),
+            Comparator::PessimisticMinor(lower) => Some(format!(
+                "means >= {} and < {}.0.0: minor and patch may increase, major may not",
+                lower,
+                lower.major + 1
+            )),
+            _ => None,
+        }
+    }
+
+    /// The range of versions this comparator spans, before the pre-release rule.
+    fn range(&self) -> VersionSet {
+        match self {
+            Comparator::Eq(v) => VersionSet::point(v),
+            Comparator::Neq(v) => VersionSet::range(Cut::BelowAll, Cut::Below(v.clone()))
+                .union(&VersionSet::range(Cut::Above(v.clone()), Cut::AboveAll)),
+            Comparator::Gt(v) => VersionSet::range(Cut::Above(v.clone()), Cut::AboveAll),
+            Comparator::Gte(v) => VersionSet::range(Cut::Below(v.clone()), Cut::AboveAll),
+            Comparator::Lt(v) => VersionSet::range(Cut::BelowAll, Cut::Below(v.clone())),
+            Comparator::Lte(v) => VersionSet::range(Cut::BelowAll, Cut::Above(v.clone())),
+            Comparator::PessimisticPatch(lower) => VersionSet::range(
+                Cut::Below(lower.clone()),
+                Cut::Below(Version::new(lower.major, lower.minor + 1, 0)),
+            ),
+            Comparator::PessimisticMinor(lower) => VersionSet::range(
+                Cut::Below(lower.clone()),
+                Cut::Below(Version::new(lower.major + 1, 0, 0)),
+            ),
+        }
+    }
+
+    /// The versions this comparator actually matches, pre-release rule included.
+    fn admitted(&self) -> Admitted {
+        let req = self.version();
+        if !req.is_prerelease() {
+            return Admitted {
+                releases: self.range(),
+                prereleases: VersionSet::empty(),
+            };
+        }
+
+        // Releases on either side of a pre-release are bounded by its X.Y.Z
+        let release = Version::new(req.major, req.minor, req.patch);
+        let releases = match self {
+            Comparator::Eq(_)
+            | Comparator::PessimisticPatch(_)
+            | Comparator::PessimisticMinor(_) => VersionSet::empty(),
+            Comparator::Neq(_) => VersionSet::full(),
+            Comparator::Gt(_) | Comparator::Gte(_) => {
+                VersionSet::range(Cut::Below(release.clone()), Cut::AboveAll)
+            }
+            Comparator::Lt(_) | Comparator::Lte(_) => {
+                VersionSet::range(Cut::BelowAll, Cut::Below(release.clone()))
+            }
+        };
+        // Pre-releases only match when they share the X.Y.Z
+        let mut first = release.clone();
+        first.pre = vec![Identifier::Numeric(0)];
+        let same_release = VersionSet::range(Cut::Below(first), Cut::Below(release));
+        Admitted {
+            releases,
+            prereleases: self.range().intersect(&same_release),
+        }
+    }
+}
+
+/// The versions some comparators admit, with releases and pre-releases kept
+/// apart: a range on the version line that covers a pre-release does not
+/// mean the pre-release is allowed, so the two only combine once intersected.
+#[derive(Debug, Clone)]
+struct Admitted {
+    releases: VersionSet,
+    prereleases: VersionSet,
+}
+
+impl Admitted {
+    fn full() -> Self {
+        Admitted {
+            releases: VersionSet::full(),
+            prereleases: VersionSet::full(),
+        }
+    }
+
+    fn intersect(&self, other: &Admitted) -> Admitted {
+        Admitted {
+            releases: self.releases.intersect(&other.releases),
+            prereleases: self.prereleases.intersect(&other.prereleases),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.releases.is_empty() && self.prereleases.is_empty()
+    }
+
+    fn is_subset(&self, other: &Admitted) -> bool {
+        self.releases.is_subset(&other.releases) && self.prereleases.is_subset(&other.prereleases)
+    }
+
+    fn to_set(&self) -> VersionSet {
+        self.releases.union(&self.prereleases)
+    }
 }
 
+impl fmt::Display for Comparator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Comparator::Eq(v) => write!(f, "= {}", v),
+            Comparator::Neq(v) => write!(f, "!= {}", v),
+            Comparator::Gt(v) => write!(f, "> {}", v),
+            Comparator::Gte(v) => write!(f, ">= {}", v),
+            Comparator::Lt(v) => write!(f, "< {}", v),
+            Comparator::Lte(v) => write!(f, "<= {}", v),
+            Comparator::PessimisticPatch(v) => write!(f, "~> {}", v),
+            Comparator::PessimisticMinor(v) => {
+                write!(f, "~> {}.{}", v.major, v.minor)?;
+                if v.is_prerelease() {
+                    let pre: Vec<String> = v.pre.iter().map(|i| i.to_string()).collect();
+                    write!(f, "-{}", pre.join("."))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// How a single comparator judged a version, as shown by `tfg explain`.
 #[derive(Debug)]
-pub struct VersionConstraint {
+pub struct Evaluation {
+    pub comparator: String,
+    pub range: VersionSet,
+    pub passed: bool,
+    /// Extra detail on how the comparator reads, or why it rejected the version.
+    pub note: Option<String>,
+}
+
+/// One constraint string as written, e.g. `required_version` in a single file.
+#[derive(Debug, Clone)]
+pub struct Requirement {
+    pub text: String,
+    /// Where the constraint came from (usually a file path), if known.
+    pub source: Option<String>,
+    /// Where in that file the constraint string is written, if known.
+    pub location: Option<Location>,
     comparators: Vec<Comparator>,
 }
 
+impl Requirement {
+    pub fn to_set(&self) -> VersionSet {
+        self.admitted().to_set()
+    }
+
+    fn admitted(&self) -> Admitted {
+        self.comparators
+            .iter()
+            .fold(Admitted::full(), |acc, c| acc.intersect(&c.admitted()))
+    }
+
+    /// Judge `v` against each comparator separately.
+    pub fn evaluate(&self, v: &Version) -> Vec<Evaluation> {
+        self.comparators
+            .iter()
+            .map(|c| Evaluation {
+                comparator: c.to_string(),
+                range: c.range(),
+                passed: c.matches(v),
+                note: c.note(v),
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.source {
+            Some(ref source) => write!(f, "{} in {}", self.text, source),
+            None => f.write_str(&self.text),
+        }
+    }
+}
+
+/// A set of requirements that must all hold, like Terraform's combined
+/// `required_version` across every file of a module.
+#[derive(Debug, Clone)]
+pub struct VersionConstraint {
+    requirements: Vec<Requirement>,
+}
+
 impl VersionConstraint {
+    /// Parse a comma-separated list of comparators. Errors are a `ParseError`
+    /// locating the comparator at fault.
     pub fn parse(s: &str) -> Result<Self> {
         let mut comparators = Vec::new();
+        let mut offset = 0;
         for part in s.split(',') {
+            let start = offset + (part.len() - part.trim_start().len());
+            offset += part.len() + 1;
             let part = part.trim();
             if part.is_empty() {
                 continue;
             }
-            comparators.push(parse_single(part)?);
+            let comparator = parse_single(part).map_err(|e| ParseError {
+                message: format!("{:#}", e),
+                range: start..start + part.len(),
+            })?;
+            comparators.push(comparator);
         }
         if comparators.is_empty() {
-            bail!("empty version constraint");
+            return Err(ParseError {
+                message: "empty version constraint".to_string(),
+                range: 0..s.len(),
+            }
+            .into());
         }
-        Ok(VersionConstraint { comparators })
+        Ok(VersionConstraint {
+            requirements: vec![Requirement {
+                text: s.trim().to_string(),
+                source: None,
+                location: None,
+                comparators,
+            }],
+        })
+    }
+
+    /// Attach a source (e.g. the file it was read from) to every requirement.
+    pub fn with_source(mut self, source: &str) -> Self {
+        for req in &mut self.requirements {
+            req.source = Some(source.to_string());
+        }
+        self
+    }
+
+    /// Attach the place the constraint string was read from to every requirement.
+    pub fn with_location(mut self, location: Location) -> Self {
+        for req in &mut self.requirements {
+            req.location = Some(location.clone());
+        }
+        self
+    }
+
+    /// Combine two constraints so that a version must satisfy both.
+    pub fn intersect(mut self, other: VersionConstraint) -> Self {
+        self.requirements.extend(other.requirements);
+        self
+    }
+
+    pub fn requirements(&self) -> &[Requirement] {
+        &self.requirements
     }
 
     pub fn matches(&self, version: &Version) -> bool {
-        self.comparators.iter().all(|c| c.matches(version))
+        self.requirements
+            .iter()
+            .flat_map(|r| &r.comparators)
+            .all(|c| c.matches(version))
+    }
+
+    /// The allowed versions as a normalized union of intervals.
+    pub fn to_set(&self) -> VersionSet {
+        self.admitted().to_set()
+    }
+
+    fn admitted(&self) -> Admitted {
+        self.requirements
+            .iter()
+            .fold(Admitted::full(), |acc, r| acc.intersect(&r.admitted()))
+    }
+
+    /// If no version can satisfy every requirement, return the requirements
+    /// that together rule everything out.
+    pub fn conflicting(&self) -> Option<Vec<&Requirement>> {
+        if !self.admitted().is_empty() {
+            return None;
+        }
+
+        // Prefer pointing at one or two requirements when that already explains it
+        let sets: Vec<Admitted> = self.requirements.iter().map(|r| r.admitted()).collect();
+        for i in 0..sets.len() {
+            for j in i..sets.len() {
+                if sets[i].intersect(&sets[j]).is_empty() {
+                    let mut culprits = vec![&self.requirements[i]];
+                    if i != j {
+                        culprits.push(&self.requirements[j]);
+                    }
+                    return Some(culprits);
+                }
+            }
+        }
+        Some(self.requirements.iter().collect())
     }
 
-    /// Pick the best (highest) version from candidates that satisfies this constraint.
-    pub fn best_match<'a>(&self, candidates: &'a [(Version, String)]) -> Option<&'a (Version, String)> {
-        candidates
+    /// Requirements that do not narrow the allowed range, because the
+    /// requirements written differently already imply them.
+    pub fn redundant(&self) -> Vec<&Requirement> {
+        self.requirements
             .iter()
-            .filter(|(v, _)| self.matches(v))
-            .max_by(|(a, _), (b, _)| a.cmp(b))
+            .filter(|req| {
+                let others: Vec<&Requirement> = self
+                    .requirements
+                    .iter()
+                    .filter(|o| o.text != req.text)
+                    .collect();
+                !others.is_empty()
+                    && others
+                        .iter()
+                        .fold(Admitted::full(), |acc, o| acc.intersect(&o.admitted()))
+                        .is_subset(&req.admitted())
+            })
+            .collect()
+    }
+
+    /// Pick the best version from candidates that satisfies this constraint,
+    /// according to `strategy`. Ties go to the earliest candidate.
+    pub fn best_match<'a>(
+        &self,
+        candidates: &'a [(Version, String)],
+        strategy: &Strategy,
+    ) -> Option<&'a (Version, String)> {
+        let mut best: Option<&'a (Version, String)> = None;
+        for candidate in candidates.iter().filter(|(v, _)| self.matches(v)) {
+            let better = match best {
+                None => true,
+                Some((current, _)) => strategy.prefers(&candidate.0, current),
+            };
+            if better {
+                best = Some(candidate);
+            }
+        }
+        best
+    }
+}
+
+/// How to choose among several versions that satisfy a constraint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Strategy {
+    Highest,
+    Lowest,
+    /// Closest to the given version (e.g. the one already installed), higher on ties.
+    Closest(Version),
+    /// Highest, but only after candidates from stable release branches have
+    /// been exhausted. Ordering itself is the same as `Highest`.
+    PreferStable,
+}
+
+impl Strategy {
+    /// Whether `a` is strictly preferred over `b`.
+    fn prefers(&self, a: &Version, b: &Version) -> bool {
+        match self {
+            Strategy::Highest | Strategy::PreferStable => a > b,
+            Strategy::Lowest => a < b,
+            Strategy::Closest(target) => match distance(a, target).cmp(&distance(b, target)) {
+                Ordering::Less => true,
+                Ordering::Greater => false,
+                Ordering::Equal => a > b,
+            },
+        }
+    }
+
+    /// Whether some version no higher than `below` could still be preferred
+    /// over `best`, so that a search going down through versions has to go on.
+    pub fn could_improve_below(&self, best: &Version, below: &Version) -> bool {
+        match self {
+            Strategy::Highest | Strategy::PreferStable => below > best,
+            Strategy::Lowest => true,
+            Strategy::Closest(target) => {
+                // The nearest any version up to `below` can get to `target`
+                let nearest = if below.major < target.major {
+                    (target.major - below.major, 0, 0)
+                } else if below.major == target.major && below.minor < target.minor {
+                    (0, target.minor - below.minor, 0)
+                } else if below.major == target.major
+                    && below.minor == target.minor
+                    && below.patch < target.patch
+                {
+                    (0, 0, target.patch - below.patch)
+                } else {
+                    (0, 0, 0)
+                };
+                match nearest.cmp(&distance(best, target)) {
+                    Ordering::Less => true,
+                    Ordering::Greater => false,
+                    Ordering::Equal => below > best,
+                }
+            }
+        }
+    }
+}
+
+/// How far `v` is from `target`, release segment by segment.
+fn distance(v: &Version, target: &Version) -> (u64, u64, u64) {
+    (
+        v.major.abs_diff(target.major),
+        v.minor.abs_diff(target.minor),
+        v.patch.abs_diff(target.patch),
+    )
+}
+
+impl fmt::Display for VersionConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut texts: Vec<&str> = Vec::new();
+        for req in &self.requirements {
+            if !texts.contains(&req.text.as_str()) {
+                texts.push(&req.text);
+            }
+        }
+        f.write_str(&texts.join(", "))
     }
 }
 
+/// A constraint string that failed to parse, and the bytes of it at fault.
+#[derive(Debug)]
+pub struct ParseError {
+    pub message: String,
+    pub range: Range<usize>,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 fn parse_single(s: &str) -> Result<Comparator> {
     let s = s.trim();
 
     if let Some(rest) = s.strip_prefix("~>") {
         let rest = rest.trim();
-        let parts: Vec<&str> = rest.split('.').collect();
-        match parts.len() {
-            2 => {
-                let major: u64 = parts[0].parse().context("invalid major")?;
-                let minor: u64 = parts[1].parse().context("invalid minor")?;
-                Ok(Comparator::PessimisticMinor { major, minor })
-            }
-            3 => {
-                let major: u64 = parts[0].parse().context("invalid major")?;
-                let minor: u64 = parts[1].parse().context("invalid minor")?;
-                let patch: u64 = parts[2].parse().context("invalid patch")?;
-                Ok(Comparator::PessimisticPatch {
-                    major,
-                    minor,
-                    patch,
-                })
-            }
+        let core = rest.split(['-', '+']).next().unwrap_or(rest);
+        match core.split('.').count() {
+            2 => Ok(Comparator::PessimisticMinor(Version::parse(rest)?)),
+            3 => Ok(Comparator::PessimisticPatch(Version::parse(rest)?)),
             _ => bail!("invalid pessimistic constraint: {}", s),
         }
     } else if let Some(rest) = s.strip_prefix(">=") {
@@ -220,4 +749,195 @@ mod tests {
         assert!(c.matches(&Version::parse("1.5.0").unwrap()));
         assert!(!c.matches(&Version::parse("1.5.1").unwrap()));
     }
+
+    #[test]
+    fn test_prerelease_ordering() {
+        let v = |s| Version::parse(s).unwrap();
+        assert!(v("1.6.0-alpha20230816") < v("1.6.0-beta1"));
+        assert!(v("1.6.0-beta1") < v("1.6.0-beta2"));
+        assert!(v("1.6.0-beta2") < v("1.6.0-rc1"));
+        assert!(v("1.6.0-rc1") < v("1.6.0"));
+        assert!(v("1.6.0-rc.2") < v("1.6.0-rc.10"));
+        assert!(v("1.6.0-rc.1") < v("1.6.0-rc.1.1"));
+        assert!(v("1.6.0-1") < v("1.6.0-alpha"));
+        assert!(v("1.5.7") < v("1.6.0-alpha1"));
+        assert_eq!(v("1.6.0+abc"), v("1.6.0+def"));
+        assert_eq!(v("1.6.0-beta1+abc").to_string(), "1.6.0-beta1+abc");
+        assert!(Version::parse("1.6.0-").is_err());
+        assert!(Version::parse("1.6.0-beta_1").is_err());
+    }
+
+    #[test]
+    fn test_prerelease_matching() {
+        let v = |s| Version::parse(s).unwrap();
+
+        // Release-only constraints never admit pre-releases
+        let c = VersionConstraint::parse(">= 1.5.0").unwrap();
+        assert!(!c.matches(&v("1.6.0-beta1")));
+        assert!(c.matches(&v("1.6.0")));
+
+        // A pre-release constraint admits pre-releases of the same X.Y.Z only
+        let c = VersionConstraint::parse(">= 1.6.0-beta1").unwrap();
+        assert!(c.matches(&v("1.6.0-beta1")));
+        assert!(c.matches(&v("1.6.0-rc1")));
+        assert!(c.matches(&v("1.6.0")));
+        assert!(c.matches(&v("1.7.0")));
+        assert!(!c.matches(&v("1.6.0-alpha1")));
+        assert!(!c.matches(&v("1.7.0-beta1")));
+
+        // Pessimistic pre-release constraints only admit pre-releases
+        let c = VersionConstraint::parse("~> 1.6.0-beta1").unwrap();
+        assert!(c.matches(&v("1.6.0-beta2")));
+        assert!(!c.matches(&v("1.6.0")));
+        assert!(!c.matches(&v("1.6.1")));
+
+        let c = VersionConstraint::parse("= 1.6.0-beta1").unwrap();
+        assert!(c.matches(&v("1.6.0-beta1+build.5")));
+        assert!(!c.matches(&v("1.6.0")));
+    }
+
+    #[test]
+    fn test_intersect() {
+        let a = VersionConstraint::parse(">= 1.3").unwrap().with_source("a.tf");
+        let b = VersionConstraint::parse("~> 1.5").unwrap().with_source("b.tf");
+        let c = a.intersect(b);
+        assert!(c.conflicting().is_none());
+        assert!(c.matches(&Version::parse("1.5.0").unwrap()));
+        assert!(!c.matches(&Version::parse("1.4.0").unwrap()));
+        assert_eq!(c.to_string(), ">= 1.3, ~> 1.5");
+        assert_eq!(c.to_set().to_string(), "[1.5.0, 2.0.0)");
+        let redundant: Vec<_> = c.redundant().iter().map(|r| r.text.as_str()).collect();
+        assert_eq!(redundant, [">= 1.3"]);
+    }
+
+    #[test]
+    fn test_to_set() {
+        let set = |s| VersionConstraint::parse(s).unwrap().to_set().to_string();
+        assert_eq!(set("~> 1.5.2"), "[1.5.2, 1.6.0)");
+        assert_eq!(set("> 1.0, <= 1.4, != 1.2.0"), "(1.0.0, 1.2.0) | (1.2.0, 1.4.0]");
+        assert_eq!(set("1.5.0"), "{1.5.0}");
+        assert_eq!(set(">= 2.0, < 1.0"), "{}");
+        assert_eq!(set(">= 1.6.0-beta1"), "[1.6.0-beta1, +inf)");
+        assert_eq!(set(">= 1.5.0-beta1, < 1.5.0-rc1"), "[1.5.0-beta1, 1.5.0-rc1)");
+        // `< 1.6.0` names no pre-release, so it drops those of 1.5.0
+        assert_eq!(set(">= 1.5.0-beta1, < 1.6.0"), "[1.5.0, 1.6.0)");
+        // Only pre-releases of 1.6.0 lie in between, and `< 1.6.0` rules them out
+        assert_eq!(set(">= 1.6.0-beta1, < 1.6.0"), "{}");
+
+        let pinned = VersionConstraint::parse("~> 1.5.0").unwrap().to_set();
+        let allowed = VersionConstraint::parse(">= 1.3, < 2.0").unwrap().to_set();
+        assert!(pinned.is_subset(&allowed));
+        assert!(!allowed.is_subset(&pinned));
+    }
+
+    #[test]
+    fn test_conflicting() {
+        let c = VersionConstraint::parse(">= 1.0")
+            .unwrap()
+            .with_source("a.tf")
+            .intersect(VersionConstraint::parse(">= 1.6").unwrap().with_source("b.tf"))
+            .intersect(VersionConstraint::parse("< 1.5").unwrap().with_source("c.tf"));
+        let sources: Vec<_> = c
+            .conflicting()
+            .unwrap()
+            .iter()
+            .map(|r| r.source.as_deref().unwrap())
+            .collect();
+        assert_eq!(sources, ["b.tf", "c.tf"]);
+
+        // Touching bounds only conflict when one of them is exclusive
+        let c = VersionConstraint::parse(">= 1.5.0")
+            .unwrap()
+            .intersect(VersionConstraint::parse("<= 1.5.0").unwrap());
+        assert!(c.conflicting().is_none());
+        let c = VersionConstraint::parse(">= 1.5.0")
+            .unwrap()
+            .intersect(VersionConstraint::parse("< 1.5.0").unwrap());
+        assert!(c.conflicting().is_some());
+
+        // A single remaining version can still be excluded
+        let c = VersionConstraint::parse("= 1.5.0")
+            .unwrap()
+            .with_source("a.tf")
+            .intersect(VersionConstraint::parse("!= 1.5.0").unwrap().with_source("b.tf"));
+        assert_eq!(c.conflicting().unwrap().len(), 2);
+
+        // The pre-release rule counts: the range between is only pre-releases
+        // that `< 1.6.0` does not name
+        let c = VersionConstraint::parse(">= 1.6.0-beta1")
+            .unwrap()
+            .with_source("a.tf")
+            .intersect(VersionConstraint::parse("< 1.6.0").unwrap().with_source("b.tf"));
+        assert_eq!(c.conflicting().unwrap().len(), 2);
+        let c = VersionConstraint::parse(">= 1.6.0-beta1")
+            .unwrap()
+            .intersect(VersionConstraint::parse("<= 1.6.0-rc1").unwrap());
+        assert!(c.conflicting().is_none());
+        let c = VersionConstraint::parse("~> 1.6.0-beta1")
+            .unwrap()
+            .intersect(VersionConstraint::parse(">= 1.6.0").unwrap());
+        assert!(c.conflicting().is_some());
+    }
+
+    #[test]
+    fn test_best_match_strategies() {
+        let candidates: Vec<(Version, String)> = ["1.4.6", "1.5.7", "1.6.2", "1.5.7", "1.9.0"]
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (Version::parse(v).unwrap(), format!("c{}", i)))
+            .collect();
+        let c = VersionConstraint::parse(">= 1.5, < 1.9").unwrap();
+        let pick = |strategy| c.best_match(&candidates, &strategy).unwrap().1.clone();
+
+        assert_eq!(pick(Strategy::Highest), "c2");
+        assert_eq!(pick(Strategy::Lowest), "c1");
+        assert_eq!(pick(Strategy::Closest(Version::parse("1.5.2").unwrap())), "c1");
+        assert_eq!(pick(Strategy::Closest(Version::parse("1.4.0").unwrap())), "c1");
+        assert_eq!(pick(Strategy::Closest(Version::parse("1.6.0").unwrap())), "c2");
+    }
+
+    #[test]
+    fn test_could_improve_below() {
+        let v = |s: &str| Version::parse(s).unwrap();
+        assert!(!Strategy::Highest.could_improve_below(&v("1.5.7"), &v("1.5.7")));
+        assert!(Strategy::Highest.could_improve_below(&v("1.5.7"), &v("1.6.0")));
+        assert!(Strategy::Lowest.could_improve_below(&v("1.5.7"), &v("1.0.0")));
+
+        let closest = Strategy::Closest(v("1.5.2"));
+        assert!(closest.could_improve_below(&v("1.6.0"), &v("1.5.9")));
+        assert!(closest.could_improve_below(&v("1.5.4"), &v("1.5.1")));
+        // Nothing below 1.5.0 is nearer to 1.5.2 than 1.5.3, or as near as 1.5.0
+        assert!(!closest.could_improve_below(&v("1.5.3"), &v("1.4.9")));
+        assert!(!closest.could_improve_below(&v("1.5.0"), &v("1.5.0")));
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let c = VersionConstraint::parse(">= 1.3, ~> 1.5").unwrap();
+        let evals = c.requirements()[0].evaluate(&Version::parse("1.4.2").unwrap());
+        assert_eq!(evals.len(), 2);
+        assert_eq!(evals[0].comparator, ">= 1.3.0");
+        assert!(evals[0].passed);
+        assert!(evals[0].note.is_none());
+        assert_eq!(evals[1].comparator, "~> 1.5");
+        assert_eq!(evals[1].range.to_string(), "[1.5.0, 2.0.0)");
+        assert!(!evals[1].passed);
+        assert!(evals[1].note.as_deref().unwrap().contains("< 2.0.0"));
+
+        let evals = c.requirements()[0].evaluate(&Version::parse("1.6.0-beta1").unwrap());
+        assert!(evals.iter().all(|e| !e.passed));
+        assert!(evals[0].note.as_deref().unwrap().starts_with("pre-release"));
+    }
+
+    #[test]
+    fn test_parse_error_range() {
+        let range = |s: &str| {
+            let err = VersionConstraint::parse(s).unwrap_err();
+            let err = err.downcast_ref::<ParseError>().unwrap();
+            s[err.range.clone()].to_string()
+        };
+        assert_eq!(range(">= 1.3,  ~> 1.x , < 2.0"), "~> 1.x");
+        assert_eq!(range("=> 1.5"), "=> 1.5");
+        assert_eq!(range(" , "), " , ");
+    }
 }
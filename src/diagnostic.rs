@@ -0,0 +1,151 @@
+use std::fmt;
+use std::ops::Range;
+
+/// A stretch of a source file, resolved to its line so it can be shown without
+/// keeping the whole file around. Spans crossing a line end are cut off there.
+#[derive(Debug, Clone)]
+pub struct Location {
+    pub file: String,
+    /// 1-based.
+    pub line: usize,
+    /// The source line the span is on.
+    text: String,
+    /// Byte range of the span within `text`.
+    range: Range<usize>,
+}
+
+impl Location {
+    /// The bytes `range` of `content`, which was read from `file`.
+    pub fn new(file: &str, content: &str, range: Range<usize>) -> Location {
+        let start = floor_char_boundary(content, range.start.min(content.len()));
+        let line_start = content[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = content[start..].find('\n').map_or(content.len(), |i| start + i);
+        let text = content[line_start..line_end].trim_end_matches('\r');
+        let end = floor_char_boundary(content, range.end.clamp(start, line_start + text.len()));
+        Location {
+            file: file.to_string(),
+            line: content[..line_start].matches('\n').count() + 1,
+            text: text.to_string(),
+            range: start - line_start..end - line_start,
+        }
+    }
+
+    /// The part `range` of this span, relative to its start, e.g. one
+    /// comparator of a constraint string.
+    pub fn sub(&self, range: Range<usize>) -> Location {
+        let start = (self.range.start + range.start).min(self.range.end);
+        let end = (self.range.start + range.end).clamp(start, self.range.end);
+        Location {
+            range: start..end,
+            ..self.clone()
+        }
+    }
+
+    /// 1-based, counted in characters.
+    pub fn column(&self) -> usize {
+        self.text[..self.range.start].chars().count() + 1
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column())
+    }
+}
+
+fn floor_char_boundary(s: &str, mut i: usize) -> usize {
+    while !s.is_char_boundary(i) {
+        i -= 1;
+    }
+    i
+}
+
+/// An error that points into the configuration, rendered like a compiler
+/// error: the message, then each labelled span with its source line.
+#[derive(Debug)]
+pub struct Diagnostic {
+    message: String,
+    labels: Vec<(Location, String)>,
+    notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            message: message.into(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn label(mut self, location: Location, message: impl Into<String>) -> Diagnostic {
+        self.labels.push((location, message.into()));
+        self
+    }
+
+    pub fn note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)?;
+        let width = self
+            .labels
+            .iter()
+            .map(|(l, _)| l.line.to_string().len())
+            .max()
+            .unwrap_or(0);
+
+        for (location, message) in &self.labels {
+            let pad = " ".repeat(width);
+            let before = location.text[..location.range.start].chars().count();
+            let marked = location.text[location.range.clone()].chars().count().max(1);
+            write!(f, "\n{}--> {}", pad, location)?;
+            write!(f, "\n{} |", pad)?;
+            write!(f, "\n{:>width$} | {}", location.line, location.text, width = width)?;
+            write!(f, "\n{} | {}{}", pad, " ".repeat(before), "^".repeat(marked))?;
+            if !message.is_empty() {
+                write!(f, " {}", message)?;
+            }
+        }
+        for note in &self.notes {
+            write!(f, "\n{} = note: {}", " ".repeat(width), note)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let content = "terraform {\n  required_version = \">= 1.x\"\n}\n";
+        let start = content.find(">=").unwrap();
+        let value = Location::new("main.tf", content, start..start + 6);
+        assert_eq!(value.to_string(), "main.tf:2:23");
+
+        let diagnostic = Diagnostic::new("invalid required_version \">= 1.x\"")
+            .label(value.sub(3..6), "invalid version format: 1.x")
+            .note("required in main.tf");
+        assert_eq!(
+            diagnostic.to_string(),
+            r#"invalid required_version ">= 1.x"
+ --> main.tf:2:26
+  |
+2 |   required_version = ">= 1.x"
+  |                          ^^^ invalid version format: 1.x
+  = note: required in main.tf"#
+        );
+
+        // Spans running past the end of their line stop there
+        let block = Location::new("main.tf", content, 0..content.len());
+        assert_eq!(block.sub(0..100).range, 0..11);
+    }
+}
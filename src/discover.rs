@@ -0,0 +1,172 @@
+use crate::engine::Engine;
+use crate::hcl;
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// A Terraform root module found under the search directory.
+#[derive(Debug, Clone)]
+pub struct Root {
+    /// Path relative to the search directory, `.` for the directory itself.
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// Recursively find directories containing configuration files for `engine`
+/// (`.tf` or `.tf.json`, plus `.tofu` and `.tofu.json` for OpenTofu), or a
+/// `terragrunt.hcl`, under `dir`.
+///
+/// Hidden directories such as `.terraform/` and `.git/` are never entered, and
+/// directories that another discovered module calls as a local child module,
+/// or whose `terragrunt.hcl` another one includes, are not roots themselves.
+/// `include` and `exclude` are globs matched against each directory's path
+/// relative to `dir`; an excluded directory is skipped along with everything
+/// below it, and when `include` is non-empty a root must match one of its globs.
+pub fn find_roots(
+    dir: &Path,
+    include: &[String],
+    exclude: &[String],
+    engine: Engine,
+) -> Result<Vec<Root>> {
+    let include = compile_patterns(include)?;
+    let exclude = compile_patterns(exclude)?;
+
+    let mut roots = Vec::new();
+    walk(dir, dir, &include, &exclude, engine, &mut roots)?;
+
+    let mut called = HashSet::new();
+    for root in &roots {
+        called.extend(hcl::local_module_dirs(&root.path, engine)?);
+        called.extend(hcl::terragrunt_include_dirs(&root.path)?);
+    }
+    roots.retain(|r| match r.path.canonicalize() {
+        Ok(path) => !called.contains(&path),
+        Err(_) => true,
+    });
+
+    roots.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(roots)
+}
+
+fn compile_patterns(patterns: &[String]) -> Result<Vec<glob::Pattern>> {
+    patterns
+        .iter()
+        .map(|p| glob::Pattern::new(p).with_context(|| format!("invalid glob pattern: {}", p)))
+        .collect()
+}
+
+fn matches_any(patterns: &[glob::Pattern], name: &str) -> bool {
+    let options = glob::MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+    patterns.iter().any(|p| p.matches_with(name, options))
+}
+
+fn walk(
+    base: &Path,
+    dir: &Path,
+    include: &[glob::Pattern],
+    exclude: &[glob::Pattern],
+    engine: Engine,
+    roots: &mut Vec<Root>,
+) -> Result<()> {
+    let name = match dir.strip_prefix(base) {
+        Ok(rel) if rel.as_os_str().is_empty() => ".".to_string(),
+        Ok(rel) => rel.to_string_lossy().replace('\\', "/"),
+        Err(_) => dir.display().to_string(),
+    };
+    if matches_any(exclude, &name) {
+        return Ok(());
+    }
+
+    let mut has_tf = false;
+    let mut subdirs = Vec::new();
+    let entries =
+        std::fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry.with_context(|| format!("failed to read {}", dir.display()))?;
+        let file_type = entry.file_type().context("failed to read file type")?;
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+        if file_type.is_dir() {
+            if !file_name.starts_with('.') {
+                subdirs.push(entry.path());
+            }
+        } else if engine.config_extensions().iter().any(|e| file_name.ends_with(e))
+            || file_name == "terragrunt.hcl"
+        {
+            has_tf = true;
+        }
+    }
+
+    if has_tf && (include.is_empty() || matches_any(include, &name)) {
+        roots.push(Root {
+            name,
+            path: dir.to_path_buf(),
+        });
+    }
+
+    for subdir in subdirs {
+        walk(base, &subdir, include, exclude, engine, roots)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn test_find_roots() {
+        let base = TestDir::new("discover");
+        for dir in [
+            "stacks/app",
+            "stacks/db",
+            "envs/prod",
+            "modules/vpc",
+            "stacks/app/.terraform/modules/x",
+            ".git/hooks",
+        ] {
+            std::fs::create_dir_all(base.join(dir)).unwrap();
+            std::fs::write(base.join(dir).join("main.tf"), "").unwrap();
+        }
+        std::fs::create_dir_all(base.join("docs")).unwrap();
+        std::fs::create_dir_all(base.join("shared/tags")).unwrap();
+        std::fs::write(base.join("shared/tags/main.tf"), "").unwrap();
+        std::fs::write(
+            base.join("envs/prod/tags.tf"),
+            "module \"tags\" {\n  source = \"../../shared/tags\"\n}\n",
+        )
+        .unwrap();
+        // Terragrunt units are roots, the parent config they include is not
+        std::fs::create_dir_all(base.join("live/app")).unwrap();
+        std::fs::write(base.join("live/terragrunt.hcl"), "").unwrap();
+        std::fs::write(
+            base.join("live/app/terragrunt.hcl"),
+            "include {\n  path = find_in_parent_folders()\n}\n",
+        )
+        .unwrap();
+
+        let names = |include: &[&str], exclude: &[&str]| -> Vec<String> {
+            let include: Vec<String> = include.iter().map(|s| s.to_string()).collect();
+            let exclude: Vec<String> = exclude.iter().map(|s| s.to_string()).collect();
+            find_roots(&base, &include, &exclude, Engine::Terraform)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.name)
+                .collect()
+        };
+
+        assert_eq!(
+            names(&[], &[]),
+            ["envs/prod", "live/app", "modules/vpc", "stacks/app", "stacks/db"]
+        );
+        assert_eq!(
+            names(&[], &["modules", "live"]),
+            ["envs/prod", "stacks/app", "stacks/db"]
+        );
+        assert_eq!(names(&["stacks/*"], &["stacks/db"]), ["stacks/app"]);
+    }
+}
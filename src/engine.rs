@@ -0,0 +1,59 @@
+use std::fmt;
+
+/// The Terraform implementation a project targets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    #[default]
+    Terraform,
+    OpenTofu,
+}
+
+impl Engine {
+    /// The nixpkgs attribute providing the engine.
+    pub fn package(self) -> &'static str {
+        match self {
+            Engine::Terraform => "terraform",
+            Engine::OpenTofu => "opentofu",
+        }
+    }
+
+    /// The name of the engine's executable.
+    pub fn binary(self) -> &'static str {
+        match self {
+            Engine::Terraform => "terraform",
+            Engine::OpenTofu => "tofu",
+        }
+    }
+
+    /// Where nixpkgs has kept the package's expression, newest layout first.
+    pub fn nix_paths(self) -> &'static [&'static str] {
+        match self {
+            Engine::Terraform => &[
+                "pkgs/by-name/te/terraform/package.nix",
+                "pkgs/applications/networking/cluster/terraform/default.nix",
+            ],
+            Engine::OpenTofu => &[
+                "pkgs/by-name/op/opentofu/package.nix",
+                "pkgs/applications/networking/cluster/opentofu/default.nix",
+            ],
+        }
+    }
+
+    /// Extensions of the engine's configuration files. OpenTofu also reads
+    /// `.tofu` files, which take precedence over `.tf` files of the same name.
+    pub fn config_extensions(self) -> &'static [&'static str] {
+        match self {
+            Engine::Terraform => &[".tf", ".tf.json"],
+            Engine::OpenTofu => &[".tf", ".tf.json", ".tofu", ".tofu.json"],
+        }
+    }
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Engine::Terraform => "Terraform",
+            Engine::OpenTofu => "OpenTofu",
+        })
+    }
+}
@@ -0,0 +1,67 @@
+use crate::constraint::{Version, VersionConstraint};
+use crate::engine::Engine;
+use crate::flake_check;
+use crate::nixpkgs;
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+
+/// Print how `constraint` judges `version`, comparator by comparator.
+/// Returns whether the version satisfies the whole constraint.
+pub fn explain(constraint: &VersionConstraint, version: &Version) -> bool {
+    println!("Constraint: {}", constraint);
+    println!("Version:    {}", version);
+    println!();
+
+    for req in constraint.requirements() {
+        println!("{}", req);
+        for eval in req.evaluate(version) {
+            println!(
+                "  {:<16} {:<28} {}",
+                eval.comparator,
+                eval.range.to_string(),
+                if eval.passed { "pass" } else { "FAIL" }
+            );
+            if let Some(note) = eval.note {
+                println!("  {:<16} {}", "", note);
+            }
+        }
+    }
+
+    println!();
+    println!("Effective range: {}", constraint.to_set());
+    let satisfied = constraint.matches(version);
+    if satisfied {
+        println!("Result: {} satisfies the constraint", version);
+    } else {
+        println!("Result: {} does not satisfy the constraint", version);
+    }
+    satisfied
+}
+
+/// Explain `constraint` against the given version, or the version of `engine`
+/// pinned by the existing flake.nix when no version is given.
+pub fn run(
+    dir: &Path,
+    constraint: &VersionConstraint,
+    engine: Engine,
+    version: Option<&str>,
+    source: &dyn nixpkgs::NixpkgsSource,
+) -> Result<bool> {
+    let version = match version {
+        Some(v) => Version::parse(v).with_context(|| format!("invalid version: {}", v))?,
+        None => {
+            let flake_path = dir.join("flake.nix");
+            if !flake_path.exists() {
+                bail!("no version given and no flake.nix in {}", dir.display());
+            }
+            let flake_source =
+                std::fs::read_to_string(&flake_path).context("failed to read flake.nix")?;
+            match flake_check::pinned_version(dir, &flake_source, engine, source)? {
+                Some(v) => v,
+                None => bail!("could not determine the {} version pinned by flake.nix", engine),
+            }
+        }
+    };
+
+    Ok(explain(constraint, &version))
+}
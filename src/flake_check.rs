@@ -1,45 +1,42 @@
 use crate::constraint::{Version, VersionConstraint};
+use crate::engine::Engine;
 use crate::nixpkgs;
 use anyhow::{Context, Result};
 use std::path::Path;
 
 #[derive(Debug)]
 pub enum CheckResult {
-    /// Terraform is present and satisfies the constraint.
+    /// The engine is present and satisfies the constraint.
     Satisfied(Version),
-    /// Terraform is present but does not satisfy the constraint.
+    /// The engine is present but does not satisfy the constraint.
     WrongVersion(Version),
-    /// Terraform is not found in the flake.
+    /// The engine is not found in the flake.
     NotFound,
-    /// Could not determine the terraform version.
+    /// Could not determine the engine's version.
     Unknown,
 }
 
-/// Check if an existing flake.nix provides a terraform version satisfying the constraint.
-pub fn check(dir: &Path, constraint: &VersionConstraint, token: Option<&str>) -> Result<CheckResult> {
+/// Check if an existing flake.nix provides a version of `engine` satisfying the constraint.
+pub fn check(
+    dir: &Path,
+    constraint: &VersionConstraint,
+    engine: Engine,
+    source: &dyn nixpkgs::NixpkgsSource,
+) -> Result<CheckResult> {
     let flake_nix_path = dir.join("flake.nix");
     let flake_source =
         std::fs::read_to_string(&flake_nix_path).context("failed to read flake.nix")?;
 
-    // Check if terraform appears in the flake at all
-    if !has_terraform(&flake_source) {
+    // Check if the engine appears in the flake at all
+    if !has_package(&flake_source, engine.package()) {
         return Ok(CheckResult::NotFound);
     }
 
-    // Try to determine the pinned nixpkgs commit
-    let commit = find_nixpkgs_commit(dir, &flake_source)?;
-    let commit = match commit {
-        Some(c) => c,
-        None => return Ok(CheckResult::Unknown),
-    };
-
-    // Look up the terraform version at that commit
-    let version_str = match nixpkgs::terraform_version_at_commit(&commit, token)? {
+    let version = match pinned_version(dir, &flake_source, engine, source)? {
         Some(v) => v,
         None => return Ok(CheckResult::Unknown),
     };
 
-    let version = Version::parse(&version_str)?;
     if constraint.matches(&version) {
         Ok(CheckResult::Satisfied(version))
     } else {
@@ -47,16 +44,46 @@ pub fn check(dir: &Path, constraint: &VersionConstraint, token: Option<&str>) ->
     }
 }
 
-/// Check if the flake source contains terraform in buildInputs/packages.
-fn has_terraform(source: &str) -> bool {
-    // Walk the rnix CST to look for terraform identifiers in relevant contexts.
-    // As a practical heuristic, check for `terraform` as a token in the source.
+/// Look up the version of `engine` at the nixpkgs commit pinned by the flake.
+/// Returns `None` if the commit or version cannot be determined.
+pub fn pinned_version(
+    dir: &Path,
+    flake_source: &str,
+    engine: Engine,
+    source: &dyn nixpkgs::NixpkgsSource,
+) -> Result<Option<Version>> {
+    // Try to determine the pinned nixpkgs commit
+    let commit = match find_nixpkgs_commit(dir, flake_source)? {
+        Some(c) => c,
+        None => return Ok(None),
+    };
+
+    // Look up the engine's version at that commit
+    let version_str = match nixpkgs::engine_version_at_commit(engine, &commit, source)? {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+
+    Ok(Some(Version::parse(&version_str)?))
+}
+
+/// The nixpkgs commit pinned by the flake.nix in `dir`, if it can be determined.
+pub fn pinned_commit(dir: &Path) -> Result<Option<String>> {
+    let flake_source =
+        std::fs::read_to_string(dir.join("flake.nix")).context("failed to read flake.nix")?;
+    find_nixpkgs_commit(dir, &flake_source)
+}
+
+/// Check if the flake source contains `package` in buildInputs/packages.
+fn has_package(source: &str, package: &str) -> bool {
+    // Walk the rnix CST to look for the package identifier in relevant contexts.
+    // As a practical heuristic, check for it as a token in the source.
     let parse = rnix::Root::parse(source);
     let syntax = parse.syntax();
 
     for element in syntax.descendants_with_tokens() {
         if let rnix::NodeOrToken::Token(token) = element {
-            if token.kind() == rnix::SyntaxKind::TOKEN_IDENT && token.text() == "terraform" {
+            if token.kind() == rnix::SyntaxKind::TOKEN_IDENT && token.text() == package {
                 return true;
             }
         }
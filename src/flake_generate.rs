@@ -1,39 +1,243 @@
+use crate::engine::Engine;
 use anyhow::{Context, Result};
 use std::path::Path;
 
-pub fn generate(dir: &Path, commit_sha: &str) -> Result<()> {
-    let content = format!(
+/// A nixpkgs flake input pinned to a commit.
+pub struct Input {
+    pub name: String,
+    pub commit: String,
+}
+
+/// A devShell and the nixpkgs input it takes the engine from.
+pub struct Shell {
+    pub name: String,
+    pub input: String,
+    /// Provider attributes to bundle via the engine's `withPlugins`.
+    pub plugins: Vec<String>,
+    /// Further packages from the same input, e.g. `terragrunt`.
+    pub packages: Vec<String>,
+}
+
+pub fn generate(
+    dir: &Path,
+    engine: Engine,
+    commit_sha: &str,
+    plugins: &[String],
+    packages: &[String],
+) -> Result<()> {
+    generate_shells(
+        dir,
+        engine,
+        &[Input {
+            name: "nixpkgs".to_string(),
+            commit: commit_sha.to_string(),
+        }],
+        &[Shell {
+            name: "default".to_string(),
+            input: "nixpkgs".to_string(),
+            plugins: plugins.to_vec(),
+            packages: packages.to_vec(),
+        }],
+    )
+}
+
+/// Write a flake.nix with one devShell per entry in `shells`, each drawing
+/// `engine` from its own nixpkgs input.
+pub fn generate_shells(
+    dir: &Path,
+    engine: Engine,
+    inputs: &[Input],
+    shells: &[Shell],
+) -> Result<()> {
+    let content = render(engine, inputs, shells);
+
+    let path = dir.join("flake.nix");
+    std::fs::write(&path, content)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(())
+}
+
+fn render(engine: Engine, inputs: &[Input], shells: &[Shell]) -> String {
+    let input_urls: String = inputs
+        .iter()
+        .map(|i| {
+            format!(
+                "    {}.url = \"github:NixOS/nixpkgs/{}\";\n",
+                i.name, i.commit
+            )
+        })
+        .collect();
+
+    let pkgs_bindings: String = inputs
+        .iter()
+        .map(|i| {
+            format!(
+                r#"        {} = import inputs.{} {{
+          inherit system;
+          config.allowUnfree = true;
+        }};
+"#,
+                pkgs_var(&i.name),
+                i.name
+            )
+        })
+        .collect();
+
+    let shell_defs: String = shells
+        .iter()
+        .map(|s| {
+            let pkgs = pkgs_var(&s.input);
+            let packages: String = s
+                .packages
+                .iter()
+                .map(|p| format!("\n            {}.{}", pkgs, attr_name(p)))
+                .collect();
+            format!(
+                r#"        devShells.{} = {}.mkShell {{
+          buildInputs = [
+            {}{}
+          ];
+        }};
+"#,
+                attr_name(&s.name),
+                pkgs,
+                engine_package(engine, &pkgs, &s.plugins),
+                packages
+            )
+        })
+        .collect();
+
+    format!(
         r#"{{
   description = "Development environment";
 
   inputs = {{
-    nixpkgs.url = "github:NixOS/nixpkgs/{}";
-    flake-parts.url = "github:hercules-ci/flake-parts";
+{}    flake-parts.url = "github:hercules-ci/flake-parts";
   }};
 
   outputs = inputs:
     inputs.flake-parts.lib.mkFlake {{ inherit inputs; }} {{
       systems = [ "x86_64-linux" "aarch64-linux" "x86_64-darwin" "aarch64-darwin" ];
       perSystem = {{ system, ... }}: let
-        pkgs = import inputs.nixpkgs {{
-          inherit system;
-          config.allowUnfree = true;
-        }};
-      in {{
-        devShells.default = pkgs.mkShell {{
-          buildInputs = [
-            pkgs.terraform
-          ];
-        }};
-      }};
+{}      in {{
+{}      }};
     }};
 }}
 "#,
-        commit_sha
-    );
+        input_urls, pkgs_bindings, shell_defs
+    )
+}
 
-    let path = dir.join("flake.nix");
-    std::fs::write(&path, content)
-        .with_context(|| format!("failed to write {}", path.display()))?;
-    Ok(())
+/// `pkgs.terraform` (or `pkgs.opentofu`), or its `withPlugins` if providers
+/// should be bundled.
+fn engine_package(engine: Engine, pkgs: &str, plugins: &[String]) -> String {
+    let package = format!("{}.{}", pkgs, engine.package());
+    if plugins.is_empty() {
+        return package;
+    }
+    let plugin_lines: String = plugins
+        .iter()
+        .map(|p| format!("              p.{}\n", attr_name(p)))
+        .collect();
+    format!(
+        "({}.withPlugins (p: [\n{}            ]))",
+        package, plugin_lines
+    )
+}
+
+/// The `let` binding holding the package set imported from a nixpkgs input,
+/// e.g. `pkgs` for `nixpkgs` and `pkgs-1_5_7` for `nixpkgs-1_5_7`.
+fn pkgs_var(input: &str) -> String {
+    input.replacen("nixpkgs", "pkgs", 1)
+}
+
+/// Quote an attribute name unless it is a plain Nix identifier.
+pub fn attr_name(name: &str) -> String {
+    let mut chars = name.chars();
+    let is_ident = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '\'');
+    if is_ident {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_per_root_shells() {
+        let inputs = [
+            Input {
+                name: "nixpkgs".to_string(),
+                commit: "aaaa".to_string(),
+            },
+            Input {
+                name: "nixpkgs-1_3_2".to_string(),
+                commit: "bbbb".to_string(),
+            },
+        ];
+        let shells = [
+            Shell {
+                name: "default".to_string(),
+                input: "nixpkgs".to_string(),
+                plugins: Vec::new(),
+                packages: Vec::new(),
+            },
+            Shell {
+                name: "stacks-app".to_string(),
+                input: "nixpkgs".to_string(),
+                plugins: Vec::new(),
+                packages: Vec::new(),
+            },
+            Shell {
+                name: "2019-legacy".to_string(),
+                input: "nixpkgs-1_3_2".to_string(),
+                plugins: Vec::new(),
+                packages: Vec::new(),
+            },
+        ];
+        let flake = render(Engine::Terraform, &inputs, &shells);
+
+        assert!(flake.contains("    nixpkgs.url = \"github:NixOS/nixpkgs/aaaa\";\n"));
+        assert!(flake.contains("    nixpkgs-1_3_2.url = \"github:NixOS/nixpkgs/bbbb\";\n"));
+        assert!(flake.contains("        pkgs-1_3_2 = import inputs.nixpkgs-1_3_2 {\n"));
+        assert!(flake.contains("        devShells.default = pkgs.mkShell {\n"));
+        assert!(flake.contains("        devShells.stacks-app = pkgs.mkShell {\n"));
+        assert!(flake.contains("        devShells.\"2019-legacy\" = pkgs-1_3_2.mkShell {\n"));
+        assert!(flake.contains("            pkgs-1_3_2.terraform\n"));
+    }
+
+    #[test]
+    fn test_render_with_plugins_and_packages() {
+        let inputs = [Input {
+            name: "nixpkgs".to_string(),
+            commit: "aaaa".to_string(),
+        }];
+        let shells = [Shell {
+            name: "default".to_string(),
+            input: "nixpkgs".to_string(),
+            plugins: vec!["aws".to_string(), "random".to_string()],
+            packages: vec!["terragrunt".to_string()],
+        }];
+        let flake = render(Engine::Terraform, &inputs, &shells);
+
+        assert!(flake.contains(
+            r#"          buildInputs = [
+            (pkgs.terraform.withPlugins (p: [
+              p.aws
+              p.random
+            ]))
+            pkgs.terragrunt
+          ];
+"#
+        ));
+
+        let flake = render(Engine::OpenTofu, &inputs, &shells);
+        assert!(flake.contains("            (pkgs.opentofu.withPlugins (p: [\n"));
+    }
 }
@@ -1,9 +1,19 @@
+use crate::engine::Engine;
+use crate::flake_generate::attr_name;
 use anyhow::{bail, Context, Result};
 use rnix::SyntaxKind;
 use std::path::Path;
 
-/// Update an existing flake.nix: replace the nixpkgs commit and optionally add terraform.
-pub fn update(dir: &Path, new_commit: &str) -> Result<()> {
+/// Update an existing flake.nix: replace the nixpkgs commit and optionally add the engine.
+/// If `plugins` is non-empty, the engine is bundled with those providers, and
+/// any of `packages` not yet in the flake are added next to it.
+pub fn update(
+    dir: &Path,
+    engine: Engine,
+    new_commit: &str,
+    plugins: &[String],
+    packages: &[String],
+) -> Result<()> {
     let flake_path = dir.join("flake.nix");
     let source =
         std::fs::read_to_string(&flake_path).context("failed to read flake.nix")?;
@@ -13,9 +23,16 @@ pub fn update(dir: &Path, new_commit: &str) -> Result<()> {
     // Step 1: Replace the nixpkgs URL commit
     result = replace_nixpkgs_url(&result, new_commit)?;
 
-    // Step 2: Add terraform to buildInputs if not present
-    if !has_terraform_in_build_inputs(&result) {
-        result = add_terraform_to_build_inputs(&result)?;
+    // Step 2: Add the engine and the other packages to buildInputs if not present
+    for package in std::iter::once(engine.package()).chain(packages.iter().map(String::as_str)) {
+        if !has_package_in_build_inputs(&result, package) {
+            result = add_package_to_build_inputs(&result, package)?;
+        }
+    }
+
+    // Step 3: Bundle the required providers
+    if !plugins.is_empty() {
+        result = set_plugins(&result, engine, plugins)?;
     }
 
     std::fs::write(&flake_path, result)
@@ -23,6 +40,36 @@ pub fn update(dir: &Path, new_commit: &str) -> Result<()> {
     Ok(())
 }
 
+/// Bundle the providers and add missing packages, leaving the nixpkgs pin alone.
+/// Returns whether flake.nix changed.
+pub fn update_shell(
+    dir: &Path,
+    engine: Engine,
+    plugins: &[String],
+    packages: &[String],
+) -> Result<bool> {
+    let flake_path = dir.join("flake.nix");
+    let source =
+        std::fs::read_to_string(&flake_path).context("failed to read flake.nix")?;
+
+    let mut result = source.clone();
+    for package in packages {
+        if !has_package_in_build_inputs(&result, package) {
+            result = add_package_to_build_inputs(&result, package)?;
+        }
+    }
+    if !plugins.is_empty() {
+        result = set_plugins(&result, engine, plugins)?;
+    }
+
+    if result == source {
+        return Ok(false);
+    }
+    std::fs::write(&flake_path, result)
+        .with_context(|| format!("failed to write {}", flake_path.display()))?;
+    Ok(true)
+}
+
 /// Replace the nixpkgs URL in the flake source using rnix CST for precise location.
 fn replace_nixpkgs_url(source: &str, new_commit: &str) -> Result<String> {
     let parse = rnix::Root::parse(source);
@@ -52,16 +99,16 @@ fn replace_nixpkgs_url(source: &str, new_commit: &str) -> Result<String> {
     bail!("could not find nixpkgs URL in flake.nix")
 }
 
-/// Check if terraform already appears in a buildInputs list.
-fn has_terraform_in_build_inputs(source: &str) -> bool {
+/// Check if a package already appears in a buildInputs list.
+fn has_package_in_build_inputs(source: &str, package: &str) -> bool {
     let parse = rnix::Root::parse(source);
     let syntax = parse.syntax();
 
-    // Look for `terraform` identifier that's a child of a list inside buildInputs
-    // As a practical approach: find any `terraform` ident token
+    // Look for the package identifier that's a child of a list inside buildInputs
+    // As a practical approach: find any ident token with its name
     for element in syntax.descendants_with_tokens() {
         if let rnix::NodeOrToken::Token(token) = element {
-            if token.kind() == SyntaxKind::TOKEN_IDENT && token.text() == "terraform" {
+            if token.kind() == SyntaxKind::TOKEN_IDENT && token.text() == package {
                 return true;
             }
         }
@@ -69,8 +116,8 @@ fn has_terraform_in_build_inputs(source: &str) -> bool {
     false
 }
 
-/// Add `pkgs.terraform` to the buildInputs list in flake.nix.
-fn add_terraform_to_build_inputs(source: &str) -> Result<String> {
+/// Add `pkgs.<package>` to the buildInputs list in flake.nix.
+fn add_package_to_build_inputs(source: &str, package: &str) -> Result<String> {
     let parse = rnix::Root::parse(source);
     let syntax = parse.syntax();
 
@@ -97,7 +144,7 @@ fn add_terraform_to_build_inputs(source: &str) -> Result<String> {
                                     // Determine indentation from context
                                     let indent = detect_list_indent(source, pos);
                                     let insertion =
-                                        format!("{}pkgs.terraform\n{}", indent, &indent[..indent.len().saturating_sub(2)]);
+                                        format!("{}pkgs.{}\n{}", indent, package, &indent[..indent.len().saturating_sub(2)]);
                                     let mut result = String::with_capacity(source.len() + insertion.len());
                                     result.push_str(&source[..pos]);
                                     result.push_str(&insertion);
@@ -119,6 +166,67 @@ fn add_terraform_to_build_inputs(source: &str) -> Result<String> {
     bail!("could not find list in buildInputs")
 }
 
+/// Point the engine at `plugins` in every shell: rewrite each existing
+/// `X.terraform.withPlugins (...)` call, so hand-written wrapping is kept, and
+/// wrap each plain `X.terraform` in one (`opentofu` for OpenTofu).
+fn set_plugins(source: &str, engine: Engine, plugins: &[String]) -> Result<String> {
+    let parse = rnix::Root::parse(source);
+    let syntax = parse.syntax();
+    let suffix = format!(".{}", engine.package());
+
+    let plugin_list: Vec<String> = plugins.iter().map(|p| format!("p.{}", attr_name(p))).collect();
+    let with_plugins = |select: &str| {
+        format!("{}.withPlugins (p: [ {} ])", select, plugin_list.join(" "))
+    };
+
+    // In source order, and never one inside another
+    let mut edits: Vec<(rnix::TextRange, String)> = Vec::new();
+    for node in syntax.descendants() {
+        if edits.iter().any(|(range, _)| range.contains_range(node.text_range())) {
+            continue;
+        }
+        match node.kind() {
+            SyntaxKind::NODE_APPLY => {
+                let function = match node.first_child() {
+                    Some(f) if f.kind() == SyntaxKind::NODE_SELECT => f.text().to_string(),
+                    _ => continue,
+                };
+                if let Some(package) = function.strip_suffix(".withPlugins") {
+                    if package.ends_with(&suffix) {
+                        edits.push((node.text_range(), with_plugins(package)));
+                    }
+                }
+            }
+            SyntaxKind::NODE_SELECT => {
+                let text = node.text().to_string();
+                if text.ends_with(&suffix) {
+                    edits.push((node.text_range(), format!("({})", with_plugins(&text))));
+                }
+            }
+            _ => {}
+        }
+    }
+    if edits.is_empty() {
+        bail!("could not find {} in flake.nix to add providers to", engine.package());
+    }
+
+    let mut result = source.to_string();
+    for (range, replacement) in edits.iter().rev() {
+        result = replace_range(&result, *range, replacement);
+    }
+    Ok(result)
+}
+
+fn replace_range(source: &str, range: rnix::TextRange, replacement: &str) -> String {
+    let start: usize = range.start().into();
+    let end: usize = range.end().into();
+    let mut result = String::with_capacity(source.len() + replacement.len());
+    result.push_str(&source[..start]);
+    result.push_str(replacement);
+    result.push_str(&source[end..]);
+    result
+}
+
 /// Detect the indentation used for list items by looking at the context before the bracket.
 fn detect_list_indent(source: &str, bracket_pos: usize) -> String {
     // Look backwards from the bracket position to find the line start
@@ -132,3 +240,54 @@ fn detect_list_indent(source: &str, bracket_pos: usize) -> String {
         "            ".to_string()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flake(build_inputs: &str) -> String {
+        format!(
+            "{{\n  outputs = {{ nixpkgs, ... }}: {{\n    \
+             devShells.default = pkgs.mkShell {{\n      buildInputs = [\n{}      ];\n    \
+             }};\n  }};\n}}\n",
+            build_inputs
+        )
+    }
+
+    #[test]
+    fn test_set_plugins() {
+        let plugins = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        let set = |source: &str, names: &[&str]| {
+            set_plugins(source, Engine::Terraform, &plugins(names)).unwrap()
+        };
+
+        // A plain package is wrapped, and a second run leaves it as it is
+        let plain = flake("        pkgs.terraform\n");
+        let wrapped = set(&plain, &["aws", "random"]);
+        let expected = flake("        (pkgs.terraform.withPlugins (p: [ p.aws p.random ]))\n");
+        assert_eq!(wrapped, expected);
+        assert_eq!(set(&wrapped, &["aws", "random"]), wrapped);
+
+        // An existing call is rewritten, quoting names that aren't identifiers
+        let existing = flake(
+            "        (pkgs.terraform.withPlugins (p: [\n          p.\"1password\"\n        ]))\n",
+        );
+        assert_eq!(
+            set(&existing, &["1password", "aws"]),
+            flake("        (pkgs.terraform.withPlugins (p: [ p.\"1password\" p.aws ]))\n")
+        );
+
+        // Every shell gets the providers
+        let shells = flake("        pkgs.terraform\n        pkgs-1_3_2.terraform\n");
+        assert_eq!(
+            set(&shells, &["aws"]),
+            flake(
+                "        (pkgs.terraform.withPlugins (p: [ p.aws ]))\n        \
+                 (pkgs-1_3_2.terraform.withPlugins (p: [ p.aws ]))\n"
+            )
+        );
+
+        let opentofu = set_plugins(&plain, Engine::OpenTofu, &plugins(&["aws"]));
+        assert!(opentofu.unwrap_err().to_string().contains("could not find opentofu"));
+    }
+}
@@ -0,0 +1,216 @@
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A local nixpkgs clone, read through the `git` command line.
+pub struct GitRepo {
+    path: PathBuf,
+}
+
+impl GitRepo {
+    /// Open the clone at `path`, which may be a bare mirror.
+    pub fn open(path: &Path) -> Result<GitRepo> {
+        let repo = GitRepo {
+            path: path.to_path_buf(),
+        };
+        repo.git(&["rev-parse", "--git-dir"])
+            .with_context(|| format!("{} is not a git repository", path.display()))?;
+        Ok(repo)
+    }
+
+    /// Every branch with its commit SHA. Branches of a plain clone are usually
+    /// only remote-tracking (`origin/nixos-24.05`); these count under their
+    /// short name unless a local branch of that name exists.
+    pub fn branches(&self) -> Result<Vec<(String, String)>> {
+        let output = self.git(&[
+            "for-each-ref",
+            "--format=%(refname) %(objectname)",
+            "refs/heads",
+            "refs/remotes",
+        ])?;
+
+        let mut local = Vec::new();
+        let mut remote = Vec::new();
+        for line in output.lines() {
+            let (name, sha) = match line.split_once(' ') {
+                Some(pair) => pair,
+                None => continue,
+            };
+            if let Some(branch) = name.strip_prefix("refs/heads/") {
+                local.push((branch.to_string(), sha.to_string()));
+            } else if let Some((_, branch)) = name
+                .strip_prefix("refs/remotes/")
+                .and_then(|n| n.split_once('/'))
+            {
+                if branch != "HEAD" {
+                    remote.push((branch.to_string(), sha.to_string()));
+                }
+            }
+        }
+        for (branch, sha) in remote {
+            if !local.iter().any(|(b, _)| *b == branch) {
+                local.push((branch, sha));
+            }
+        }
+        Ok(local)
+    }
+
+    /// The contents of `path` at `rev`, or `None` if it doesn't exist there.
+    pub fn read_file(&self, rev: &str, path: &str) -> Result<Option<String>> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.path)
+            .args(["cat-file", "-p", &format!("{}:{}", rev, path)])
+            .output()
+            .context("failed to run git")?;
+        if !output.status.success() {
+            return Ok(None);
+        }
+        Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned()))
+    }
+
+    /// Up to `count` of the commits on `rev` that changed `path`, newest
+    /// first, after skipping the first `skip`. Each is `(sha, committer date)`,
+    /// the date as `YYYY-MM-DDTHH:MM:SSZ`.
+    pub fn history(
+        &self,
+        rev: &str,
+        path: &str,
+        skip: usize,
+        count: usize,
+    ) -> Result<Vec<(String, String)>> {
+        self.log(&[
+            &format!("--skip={}", skip),
+            &format!("--max-count={}", count),
+            rev,
+            "--",
+            path,
+        ])
+    }
+
+    /// The first parent of `rev`, or `None` for a root commit.
+    pub fn parent(&self, rev: &str) -> Result<Option<String>> {
+        let output = self.git(&["rev-list", "--parents", "--max-count=1", rev])?;
+        Ok(output.split_whitespace().nth(1).map(str::to_string))
+    }
+
+    fn log(&self, args: &[&str]) -> Result<Vec<(String, String)>> {
+        let mut log = vec![
+            "log",
+            "--format=%H %cd",
+            "--date=format-local:%Y-%m-%dT%H:%M:%SZ",
+        ];
+        log.extend(args);
+        let output = self.git(&log)?;
+        Ok(output
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(sha, date)| (sha.to_string(), date.to_string()))
+            .collect())
+    }
+
+    fn git(&self, args: &[&str]) -> Result<String> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.path)
+            .args(args)
+            .env("TZ", "UTC")
+            .output()
+            .context("failed to run git")?;
+        if !output.status.success() {
+            bail!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// Build a small nixpkgs-like repository for tests: each of `commits` is
+/// `(branch, path, contents)`, committed on top of `branch`, which starts from
+/// the previous commit if it doesn't exist yet. Returns the SHA of each commit.
+#[cfg(test)]
+pub fn fixture_repo(dir: &Path, commits: &[(&str, &str, &str)]) -> Vec<String> {
+    let git = |args: &[&str]| {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "user.name=tfg", "-c", "user.email=tfg@example.com"])
+            .args(["-c", "commit.gpgsign=false"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    };
+
+    std::fs::create_dir_all(dir).unwrap();
+    git(&["init", "-q", "-b", "master"]);
+    let mut shas = Vec::new();
+    for (branch, path, contents) in commits {
+        if shas.is_empty() {
+            git(&["checkout", "-q", "-B", branch]);
+        } else if git(&["branch", "--list", branch]).is_empty() {
+            git(&["checkout", "-q", "-b", branch]);
+        } else {
+            git(&["checkout", "-q", branch]);
+        }
+        let file = dir.join(path);
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(&file, contents).unwrap();
+        git(&["add", "-A"]);
+        git(&["commit", "-q", "-m", path]);
+        shas.push(git(&["rev-parse", "HEAD"]));
+    }
+    shas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn test_reads_branches_files_and_history() {
+        let dir = TestDir::new("git");
+        let shas = fixture_repo(
+            &dir,
+            &[
+                ("master", "a.nix", "one"),
+                ("master", "b.nix", "two"),
+                ("nixos-24.05", "a.nix", "three"),
+            ],
+        );
+        let repo = GitRepo::open(&dir).unwrap();
+
+        let mut branches = repo.branches().unwrap();
+        branches.sort();
+        let master = repo.read_file("master", "a.nix").unwrap();
+        let old = repo.read_file(&shas[0], "b.nix").unwrap();
+        let history = repo.history("nixos-24.05", "a.nix", 0, 10).unwrap();
+        let skipped = repo.history("nixos-24.05", "a.nix", 1, 10).unwrap();
+        let parent = repo.parent(&shas[2]).unwrap();
+        let root = repo.parent(&shas[0]).unwrap();
+        let not_a_repo = GitRepo::open(&dir.join("nested"));
+
+        assert_eq!(
+            branches,
+            [
+                ("master".to_string(), shas[1].clone()),
+                ("nixos-24.05".to_string(), shas[2].clone()),
+            ]
+        );
+        assert_eq!(master.as_deref(), Some("one"));
+        assert_eq!(old, None);
+        let history: Vec<&str> = history.iter().map(|(sha, _)| sha.as_str()).collect();
+        assert_eq!(history, [shas[2].as_str(), shas[0].as_str()]);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].0, shas[0]);
+        assert!(skipped[0].1.ends_with('Z'), "{}", skipped[0].1);
+        assert_eq!(parent.as_deref(), Some(shas[1].as_str()));
+        assert_eq!(root, None);
+        assert!(not_a_repo.is_err());
+    }
+}
@@ -1,61 +1,917 @@
+use crate::constraint::{ParseError, VersionConstraint};
+use crate::diagnostic::{Diagnostic, Location};
+use crate::engine::Engine;
 use anyhow::{bail, Context, Result};
-use std::path::Path;
+use hcl::edit::Span;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 
-pub fn extract_required_version(dir: &Path) -> Result<String> {
-    let pattern = dir.join("*.tf");
-    let pattern_str = pattern.to_str().context("invalid directory path")?;
+/// Which optional constraint sources to read.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Also read the registry and git modules that `terraform init` downloaded,
+    /// as listed in `.terraform/modules/modules.json`.
+    pub vendored_modules: bool,
+    /// Which configuration files to read; OpenTofu adds `*.tofu` files.
+    pub engine: Engine,
+}
+
+/// Read every `required_version` in the module and combine them into a single
+/// constraint that requires all of them to hold, as Terraform does. A module
+/// without any `required_version` yields `None`.
+///
+/// Local child modules (`source = "./..."` or `"../..."`) are followed
+/// recursively, since Terraform requires their `required_version` to hold too.
+/// Their requirements are labelled with the module path that pulled them in.
+pub fn find_required_version(dir: &Path, options: &Options) -> Result<Option<VersionConstraint>> {
+    let mut combined: Option<VersionConstraint> = None;
+    for (source, info) in read_tree(dir, options)? {
+        for v in &info.required_versions {
+            let constraint = parse_constraint(v, "required_version", &source)?;
+            combined = Some(match combined {
+                Some(c) => c.intersect(constraint),
+                None => constraint,
+            });
+        }
+    }
+
+    if let Some(ref combined) = combined {
+        ensure_satisfiable(combined)?;
+    }
+    Ok(combined)
+}
+
+/// A provider from `required_providers`, merged across every file and module
+/// that names it.
+#[derive(Debug, Clone)]
+pub struct ProviderRequirement {
+    /// Registry address without the default hostname, e.g. `hashicorp/aws`.
+    pub source: String,
+    pub constraint: Option<VersionConstraint>,
+}
+
+/// Collect `required_providers` across the module tree, the same way as
+/// `find_required_version`. Providers are keyed by source address, so
+/// different local names for the same provider are merged.
+pub fn find_required_providers(dir: &Path, options: &Options) -> Result<Vec<ProviderRequirement>> {
+    let mut providers: Vec<ProviderRequirement> = Vec::new();
+    for (source, info) in read_tree(dir, options)? {
+        for (name, address, version) in &info.providers {
+            let address = normalize_provider_source(name, address.as_deref());
+            let constraint = match version {
+                Some(v) => {
+                    let what = format!("version for provider {}", name);
+                    Some(parse_constraint(v, &what, &source)?)
+                }
+                None => None,
+            };
+
+            match providers.iter_mut().find(|p| p.source == address) {
+                Some(existing) => {
+                    if let Some(c) = constraint {
+                        existing.constraint = Some(match existing.constraint.take() {
+                            Some(e) => e.intersect(c),
+                            None => c,
+                        });
+                    }
+                }
+                None => providers.push(ProviderRequirement {
+                    source: address,
+                    constraint,
+                }),
+            }
+        }
+    }
+    Ok(providers)
+}
+
+/// The provider versions recorded in `.terraform.lock.hcl` in `dir`, each as an
+/// exact constraint.
+pub fn locked_providers(dir: &Path) -> Result<Vec<ProviderRequirement>> {
+    let path = dir.join(".terraform.lock.hcl");
+    if !path.exists() {
+        bail!(
+            "no .terraform.lock.hcl in {}; run `terraform init` first",
+            dir.display()
+        );
+    }
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let body =
+        hcl::parse(&content).with_context(|| format!("failed to parse {}", path.display()))?;
+
+    let mut providers = Vec::new();
+    for block in body.blocks() {
+        if block.identifier.as_str() != "provider" {
+            continue;
+        }
+        let address = match block.labels.first() {
+            Some(label) => label,
+            None => continue,
+        };
+        for attr in block.body.attributes() {
+            if attr.key.as_str() != "version" {
+                continue;
+            }
+            if let hcl::Expression::String(ref v) = attr.expr {
+                let constraint = VersionConstraint::parse(&format!("= {}", v))
+                    .with_context(|| {
+                        format!("invalid version \"{}\" in {}", v, path.display())
+                    })?
+                    .with_source(&path.display().to_string());
+                providers.push(ProviderRequirement {
+                    source: normalize_provider_source(address.as_str(), Some(address.as_str())),
+                    constraint: Some(constraint),
+                });
+            }
+        }
+    }
+    Ok(providers)
+}
+
+/// Terraform's default for a provider without `source` is `hashicorp/<name>`,
+/// and the public registry hostname, Terraform's or OpenTofu's, may be omitted.
+fn normalize_provider_source(name: &str, source: Option<&str>) -> String {
+    let source = source.unwrap_or(name).to_lowercase();
+    let source = source
+        .strip_prefix("registry.terraform.io/")
+        .or_else(|| source.strip_prefix("registry.opentofu.org/"))
+        .unwrap_or(&source);
+    if source.contains('/') {
+        source.to_string()
+    } else {
+        format!("hashicorp/{}", source)
+    }
+}
+
+/// Parse a constraint read from a configuration file, labelled with `source`.
+/// If it doesn't parse, the error points at the comparator at fault.
+fn parse_constraint(value: &Located, what: &str, source: &str) -> Result<VersionConstraint> {
+    let location = match value.location {
+        Some(ref l) => l,
+        None => {
+            return Ok(VersionConstraint::parse(&value.text)
+                .with_context(|| format!("invalid {} \"{}\" in {}", what, value.text, source))?
+                .with_source(source))
+        }
+    };
+    let constraint = VersionConstraint::parse(&value.text).map_err(|e| {
+        let (at, message) = match e.downcast_ref::<ParseError>() {
+            Some(p) if value.exact => (location.sub(p.range.clone()), p.message.clone()),
+            Some(p) => (location.clone(), p.message.clone()),
+            None => (location.clone(), format!("{:#}", e)),
+        };
+        Diagnostic::new(format!("invalid {} \"{}\"", what, value.text)).label(at, message)
+    })?;
+    Ok(constraint.with_source(source).with_location(location.clone()))
+}
+
+/// A string read from a configuration file, and where it is written.
+#[derive(Debug, Clone)]
+struct Located {
+    text: String,
+    /// The string's contents without quotes, or the whole expression if it was
+    /// a template or heredoc. `None` where it can't be found.
+    location: Option<Location>,
+    /// Whether `location` is `text` byte for byte, so parts of it can be
+    /// pointed at.
+    exact: bool,
+}
+
+/// What tfg reads from a single configuration file.
+#[derive(Debug, Default)]
+struct FileInfo {
+    required_versions: Vec<Located>,
+    /// `(local name, source, version)` of each entry in `required_providers`.
+    providers: Vec<(String, Option<String>, Option<Located>)>,
+    /// `(name, source)` of each `module` block.
+    modules: Vec<(String, String)>,
+}
 
-    let mut versions = Vec::new();
+impl FileInfo {
+    /// Only the last `required_version` and the last entry for each provider
+    /// and module. An override file with several `terraform` blocks merges them
+    /// one after another, so later ones win.
+    fn without_repeats(&self) -> FileInfo {
+        let mut providers: Vec<(String, Option<String>, Option<Located>)> = Vec::new();
+        for provider in self.providers.iter().rev() {
+            if !providers.iter().any(|(n, _, _)| *n == provider.0) {
+                providers.insert(0, provider.clone());
+            }
+        }
+        let mut modules: Vec<(String, String)> = Vec::new();
+        for module in self.modules.iter().rev() {
+            if !modules.iter().any(|(n, _)| *n == module.0) {
+                modules.insert(0, module.clone());
+            }
+        }
+        FileInfo {
+            required_versions: self.required_versions.last().cloned().into_iter().collect(),
+            providers,
+            modules,
+        }
+    }
+}
+
+/// Read every configuration file of the module at `dir` and of the child
+/// modules it calls, labelling each with the path of the file and the module
+/// that pulled it in.
+fn read_tree(dir: &Path, options: &Options) -> Result<Vec<(String, FileInfo)>> {
+    let mut visited = HashSet::new();
+    let mut files = Vec::new();
+    collect_files(dir, None, options.engine, &mut visited, &mut files)?;
+
+    if options.vendored_modules {
+        for (key, module_dir) in vendored_modules(dir)? {
+            // Keys are dotted module paths, e.g. "vpc.nat" for module.vpc.module.nat
+            let module_path: Vec<String> =
+                key.split('.').map(|k| format!("module.{}", k)).collect();
+            collect_files(
+                &module_dir,
+                Some(&module_path.join(".")),
+                options.engine,
+                &mut visited,
+                &mut files,
+            )?;
+        }
+    }
+    Ok(files)
+}
 
-    for entry in glob::glob(pattern_str).context("invalid glob pattern")? {
-        let path = entry.context("error reading glob entry")?;
+fn collect_files(
+    dir: &Path,
+    module_path: Option<&str>,
+    engine: Engine,
+    visited: &mut HashSet<PathBuf>,
+    files: &mut Vec<(String, FileInfo)>,
+) -> Result<()> {
+    let dir = dir
+        .canonicalize()
+        .with_context(|| format!("invalid module directory {}", dir.display()))?;
+    // Modules may be shared or even include each other; read each one once
+    if !visited.insert(dir.clone()) {
+        return Ok(());
+    }
+
+    let module_files = read_module(&dir, engine)?;
+    let mut children = Vec::new();
+    for (path, info) in &module_files {
+        for (name, source) in &info.modules {
+            if !is_local_source(source) {
+                continue;
+            }
+            let child = dir.join(source);
+            if !child.is_dir() {
+                bail!(
+                    "module \"{}\" in {} points to missing directory {}",
+                    name,
+                    path.display(),
+                    source
+                );
+            }
+            let child_path = match module_path {
+                Some(m) => format!("{}.module.{}", m, name),
+                None => format!("module.{}", name),
+            };
+            children.push((child, child_path));
+        }
+    }
+
+    for (path, info) in module_files {
+        let label = match module_path {
+            Some(m) => format!("{} ({})", path.display(), m),
+            None => path.display().to_string(),
+        };
+        files.push((label, info));
+    }
+    for (child, child_path) in children {
+        collect_files(&child, Some(&child_path), engine, visited, files)?;
+    }
+    Ok(())
+}
+
+/// Version constraints from a Terragrunt configuration.
+#[derive(Debug, Clone, Default)]
+pub struct TerragruntConstraints {
+    /// `terraform_version_constraint`
+    pub terraform: Option<VersionConstraint>,
+    /// `terragrunt_version_constraint`
+    pub terragrunt: Option<VersionConstraint>,
+}
+
+impl TerragruntConstraints {
+    /// Constraints that hold for both configurations.
+    pub fn merge(self, other: TerragruntConstraints) -> TerragruntConstraints {
+        let both = |a: Option<VersionConstraint>, b: Option<VersionConstraint>| match (a, b) {
+            (Some(a), Some(b)) => Some(a.intersect(b)),
+            (a, b) => a.or(b),
+        };
+        TerragruntConstraints {
+            terraform: both(self.terraform, other.terraform),
+            terragrunt: both(self.terragrunt, other.terragrunt),
+        }
+    }
+}
+
+/// Read the version constraints of the `terragrunt.hcl` in `dir`, following its
+/// `include` blocks. Returns `None` if there is no `terragrunt.hcl`.
+pub fn find_terragrunt_constraints(dir: &Path) -> Result<Option<TerragruntConstraints>> {
+    let path = dir.join("terragrunt.hcl");
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let mut constraints = TerragruntConstraints::default();
+    read_terragrunt_file(&path, &mut HashSet::new(), &mut constraints)?;
+    if let Some(ref c) = constraints.terraform {
+        ensure_satisfiable(c)?;
+    }
+    Ok(Some(constraints))
+}
+
+/// Fill in what `constraints` still lacks from the file at `path`, then from
+/// the files it includes. As in Terragrunt, the including file's values win,
+/// and a later include wins over an earlier one.
+fn read_terragrunt_file(
+    path: &Path,
+    visited: &mut HashSet<PathBuf>,
+    constraints: &mut TerragruntConstraints,
+) -> Result<()> {
+    let path = path
+        .canonicalize()
+        .with_context(|| format!("invalid Terragrunt file {}", path.display()))?;
+    if !visited.insert(path.clone()) {
+        return Ok(());
+    }
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let body = hcl::edit::parser::parse_body(&content)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+
+    let label = path.display().to_string();
+    for attr in body.attributes() {
+        let key = attr.key.as_str();
+        let slot = match key {
+            "terraform_version_constraint" => &mut constraints.terraform,
+            "terragrunt_version_constraint" => &mut constraints.terragrunt,
+            _ => continue,
+        };
+        if slot.is_some() {
+            continue;
+        }
+        let value = literal_from_hcl(&label, &content, &attr.value, key)?;
+        *slot = Some(parse_constraint(&value, key, &label)?);
+    }
+
+    let body = hcl::Body::from(body);
+    for included in terragrunt_includes(&path, &body)?.iter().rev() {
+        read_terragrunt_file(included, visited, constraints)?;
+    }
+    Ok(())
+}
+
+/// The files included by the Terragrunt file at `path`, whose parsed content is
+/// `body`. Includes with `merge_strategy = "no_merge"` are left out, since
+/// nothing is inherited from them.
+fn terragrunt_includes(path: &Path, body: &hcl::Body) -> Result<Vec<PathBuf>> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut includes = Vec::new();
+    for block in body.blocks() {
+        if block.identifier.as_str() != "include" {
+            continue;
+        }
+        let no_merge = block.body.attributes().any(|a| {
+            a.key.as_str() == "merge_strategy"
+                && matches!(a.expr, hcl::Expression::String(ref s) if s == "no_merge")
+        });
+        if no_merge {
+            continue;
+        }
+        for attr in block.body.attributes() {
+            if attr.key.as_str() == "path" {
+                let included = include_path(dir, &attr.expr).with_context(|| {
+                    format!("cannot resolve include path in {}", path.display())
+                })?;
+                includes.push(included);
+            }
+        }
+    }
+    Ok(includes)
+}
+
+/// Resolve an include `path`: a string relative to the including file's
+/// directory, or `find_in_parent_folders()` with an optional file name.
+fn include_path(dir: &Path, expr: &hcl::Expression) -> Result<PathBuf> {
+    match expr {
+        hcl::Expression::String(p) => Ok(dir.join(p)),
+        hcl::Expression::FuncCall(call)
+            if call.name.namespace.is_empty()
+                && call.name.name.as_str() == "find_in_parent_folders" =>
+        {
+            let name = match call.args.first() {
+                None => "terragrunt.hcl",
+                Some(hcl::Expression::String(name)) => name.as_str(),
+                Some(_) => bail!("find_in_parent_folders() is only supported with a string literal"),
+            };
+            dir.ancestors()
+                .skip(1)
+                .map(|d| d.join(name))
+                .find(|p| p.is_file())
+                .with_context(|| format!("find_in_parent_folders(\"{}\") found no such file", name))
+        }
+        _ => bail!("only string literals and find_in_parent_folders() are supported"),
+    }
+}
+
+/// Directories whose `terragrunt.hcl` the `terragrunt.hcl` in `dir` includes,
+/// as canonical paths.
+pub fn terragrunt_include_dirs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let path = dir.join("terragrunt.hcl");
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let body =
+        hcl::parse(&content).with_context(|| format!("failed to parse {}", path.display()))?;
+
+    let mut dirs = Vec::new();
+    for included in terragrunt_includes(&path, &body)? {
+        if included.file_name().is_some_and(|n| n == "terragrunt.hcl") {
+            if let Some(parent) = included.parent().and_then(|p| p.canonicalize().ok()) {
+                dirs.push(parent);
+            }
+        }
+    }
+    Ok(dirs)
+}
+
+/// Directories of the local child modules that the module in `dir` calls directly.
+pub fn local_module_dirs(dir: &Path, engine: Engine) -> Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    for (_, info) in read_module(dir, engine)? {
+        for (_, source) in info.modules {
+            if is_local_source(&source) {
+                if let Ok(child) = dir.join(&source).canonicalize() {
+                    dirs.push(child);
+                }
+            }
+        }
+    }
+    Ok(dirs)
+}
+
+#[derive(Deserialize)]
+struct ModulesManifest {
+    #[serde(rename = "Modules")]
+    modules: Vec<ManifestEntry>,
+}
+
+#[derive(Deserialize)]
+struct ManifestEntry {
+    #[serde(rename = "Key")]
+    key: String,
+    #[serde(rename = "Dir")]
+    dir: String,
+}
+
+/// Where `terraform init` lists the modules it downloaded, relative to the root
+/// module.
+const MODULES_MANIFEST: &str = ".terraform/modules/modules.json";
+
+/// The modules manifest of the root module at `dir`, if `options` asks for
+/// vendored modules but `terraform init` hasn't written one, so that none are
+/// read.
+pub fn missing_modules_manifest(dir: &Path, options: &Options) -> Option<PathBuf> {
+    let manifest_path = dir.join(MODULES_MANIFEST);
+    (options.vendored_modules && !manifest_path.exists()).then_some(manifest_path)
+}
+
+/// `(key, directory)` of every module recorded in `.terraform/modules/modules.json`
+/// under the root module at `dir`, except the root itself. Entries whose
+/// directory is gone are skipped, and a missing manifest yields nothing.
+fn vendored_modules(dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let manifest_path = dir.join(MODULES_MANIFEST);
+    if !manifest_path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = std::fs::read_to_string(&manifest_path)
+        .with_context(|| format!("failed to read {}", manifest_path.display()))?;
+    let manifest: ModulesManifest = serde_json::from_str(&content)
+        .with_context(|| format!("failed to parse {}", manifest_path.display()))?;
+
+    Ok(manifest
+        .modules
+        .into_iter()
+        .filter(|m| !m.key.is_empty())
+        .map(|m| (m.key, dir.join(m.dir)))
+        .filter(|(_, d)| d.is_dir())
+        .collect())
+}
+
+/// Terraform treats a module source as a local path only with these prefixes.
+fn is_local_source(source: &str) -> bool {
+    source.starts_with("./") || source.starts_with("../")
+}
+
+/// Read the parts of every configuration file in the module at `dir` that tfg
+/// cares about, with override files merged over the others.
+fn read_module(dir: &Path, engine: Engine) -> Result<Vec<(PathBuf, FileInfo)>> {
+    let mut files = Vec::new();
+    let mut overrides = Vec::new();
+    for path in config_files(dir, engine)? {
         let content = std::fs::read_to_string(&path)
             .with_context(|| format!("failed to read {}", path.display()))?;
 
-        // Skip files that don't mention required_version at all
-        if !content.contains("required_version") {
+        // Skip files that can't contain anything we look for
+        if !content.contains("required_") && !content.contains("module") {
             continue;
         }
 
-        let body = hcl::parse(&content)
-            .with_context(|| format!("failed to parse HCL in {}", path.display()))?;
+        let file = path.display().to_string();
+        let info = if is_json_config(&path) {
+            file_info_from_json(&file, &content)?
+        } else {
+            file_info_from_hcl(&file, &content)?
+        };
+        if is_override_file(&path) {
+            overrides.push((path, info));
+        } else {
+            files.push((path, info));
+        }
+    }
+
+    // Terraform applies override files after the others, in name order
+    overrides.sort_by(|(a, _), (b, _)| a.file_name().cmp(&b.file_name()));
+    for (path, info) in overrides {
+        let info = info.without_repeats();
+        apply_override(&mut files, &info);
+        files.push((path, info));
+    }
+    Ok(files)
+}
+
+/// Whether `path` is an override file: `override.tf`, `*_override.tf`, or the
+/// same with any other configuration extension.
+fn is_override_file(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let stem = [".tf.json", ".tofu.json", ".tf", ".tofu"]
+        .iter()
+        .find_map(|ext| name.strip_suffix(ext))
+        .unwrap_or(&name);
+    stem == "override" || stem.ends_with("_override")
+}
+
+/// Merge an override file over the files read so far, the way Terraform merges
+/// override blocks: its `required_version` replaces every earlier one, and each
+/// of its `required_providers` entries and module sources replaces the earlier
+/// ones of the same name, leaving the rest alone.
+fn apply_override(files: &mut [(PathBuf, FileInfo)], over: &FileInfo) {
+    for (_, info) in files.iter_mut() {
+        if !over.required_versions.is_empty() {
+            info.required_versions.clear();
+        }
+        info.providers
+            .retain(|(name, _, _)| !over.providers.iter().any(|(n, _, _)| n == name));
+        info.modules
+            .retain(|(name, _)| !over.modules.iter().any(|(n, _)| n == name));
+    }
+}
+
+/// The configuration files directly inside `dir`: native `*.tf` files followed
+/// by `*.tf.json` files, each group sorted by name. For OpenTofu, `*.tofu` and
+/// `*.tofu.json` files join their group, and replace a `.tf` or `.tf.json` file
+/// of the same name as OpenTofu does.
+fn config_files(dir: &Path, engine: Engine) -> Result<Vec<PathBuf>> {
+    let glob_files = |suffix: &str| -> Result<Vec<PathBuf>> {
+        let pattern = dir.join(format!("*{}", suffix));
+        let pattern_str = pattern.to_str().context("invalid directory path")?;
+        glob::glob(pattern_str)
+            .context("invalid glob pattern")?
+            .map(|entry| entry.context("error reading glob entry"))
+            .collect()
+    };
+
+    let mut files = Vec::new();
+    for (suffix, tofu_suffix) in [(".tf", ".tofu"), (".tf.json", ".tofu.json")] {
+        let mut group = glob_files(suffix)?;
+        if engine == Engine::OpenTofu {
+            group.retain(|path| {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                let stem = name.strip_suffix(suffix).unwrap_or(&name);
+                !dir.join(format!("{}{}", stem, tofu_suffix)).is_file()
+            });
+            group.extend(glob_files(tofu_suffix)?);
+            group.sort();
+        }
+        files.extend(group);
+    }
+    Ok(files)
+}
+
+fn is_json_config(path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    path.ends_with(".tf.json") || path.ends_with(".tofu.json")
+}
+
+/// `required_version` values from the `terraform` blocks and sources of the
+/// `module` blocks of a native HCL file. `file` is the path shown in errors.
+fn file_info_from_hcl(file: &str, content: &str) -> Result<FileInfo> {
+    let body = hcl::edit::parser::parse_body(content).map_err(|e| {
+        let offset = e.location().offset();
+        Diagnostic::new(format!("failed to parse HCL in {}", file))
+            .label(Location::new(file, content, offset..offset + 1), e.message())
+    })?;
+    let string = |expr: &hcl::edit::expr::Expression| string_from_hcl(file, content, expr);
 
-        for block in body.blocks() {
-            if block.identifier.to_string() == "terraform" {
+    let mut info = FileInfo::default();
+    for block in body.blocks() {
+        match block.ident.as_str() {
+            "terraform" => {
                 for attr in block.body.attributes() {
-                    if attr.key.to_string() == "required_version" {
-                        if let hcl::Expression::String(ref v) = attr.expr {
-                            versions.push((v.clone(), path.display().to_string()));
+                    if attr.key.as_str() == "required_version" {
+                        let v = literal_from_hcl(file, content, &attr.value, "required_version")?;
+                        info.required_versions.push(v);
+                    }
+                }
+                for inner in block.body.blocks() {
+                    if inner.ident.as_str() == "required_providers" {
+                        for attr in inner.body.attributes() {
+                            let (source, version) = provider_from_hcl(&attr.value, string);
+                            info.providers.push((attr.key.as_str().to_string(), source, version));
+                        }
+                    }
+                }
+            }
+            "module" => {
+                let Some(name) = block.labels.first() else {
+                    continue;
+                };
+                for attr in block.body.attributes() {
+                    if attr.key.as_str() == "source" {
+                        if let Some(source) = string(&attr.value) {
+                            info.modules.push((name.as_str().to_string(), source.text));
                         }
                     }
                 }
             }
+            _ => {}
         }
     }
+    Ok(info)
+}
 
-    match versions.len() {
-        0 => bail!("no required_version found in any .tf files in {}", dir.display()),
-        1 => Ok(versions.into_iter().next().unwrap().0),
-        _ => {
-            let first = &versions[0].0;
-            if versions.iter().all(|(v, _)| v == first) {
-                Ok(versions.into_iter().next().unwrap().0)
-            } else {
-                let details: Vec<String> = versions
-                    .iter()
-                    .map(|(v, f)| format!("  {} in {}", v, f))
-                    .collect();
-                bail!(
-                    "multiple conflicting required_version constraints found:\n{}",
-                    details.join("\n")
-                )
+/// The value of a string literal, located in `content`.
+fn string_from_hcl(
+    file: &str,
+    content: &str,
+    expr: &hcl::edit::expr::Expression,
+) -> Option<Located> {
+    match expr {
+        hcl::edit::expr::Expression::String(s) => {
+            // The span includes the quotes
+            let span = s.span().map(|span| span.start + 1..span.end - 1);
+            Some(Located {
+                text: s.value().clone(),
+                location: span.clone().map(|span| Location::new(file, content, span)),
+                exact: span.and_then(|span| content.get(span)) == Some(s.value().as_str()),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// A string that `what` is set to and that is known without evaluating
+/// anything, as Terraform requires for `required_version`. Templates and
+/// heredocs count as long as they don't interpolate anything; references,
+/// function calls and values of other types are errors.
+fn literal_from_hcl(
+    file: &str,
+    content: &str,
+    expr: &hcl::edit::expr::Expression,
+    what: &str,
+) -> Result<Located> {
+    use hcl::eval::Evaluate;
+
+    if let Some(s) = string_from_hcl(file, content, expr) {
+        return Ok(s);
+    }
+    let location = match expr.span() {
+        Some(span) => Location::new(file, content, span),
+        None => bail!("{} in {} must be a literal string", what, file),
+    };
+    let diagnostic = match hcl::Expression::from(expr.clone()).evaluate(&Default::default()) {
+        Ok(hcl::Value::String(text)) => {
+            return Ok(Located {
+                text,
+                location: Some(location),
+                exact: false,
+            })
+        }
+        Ok(value) => Diagnostic::new(format!("{} must be a string", what)).label(
+            location,
+            format!("this is {}", value_kind(&value)),
+        ),
+        Err(_) => Diagnostic::new(format!("{} must be a literal string", what)).label(
+            location,
+            "references, function calls and interpolations can't be used here",
+        ),
+    };
+    let diagnostic = match expr {
+        hcl::edit::expr::Expression::Number(_) => {
+            diagnostic.note(format!("quote it: \"{}\"", expr.to_string().trim()))
+        }
+        _ => diagnostic,
+    };
+    Err(diagnostic.into())
+}
+
+/// The type of `value`, as in "this is a number".
+fn value_kind(value: &hcl::Value) -> &'static str {
+    match value {
+        hcl::Value::Null => "null",
+        hcl::Value::Bool(_) => "a bool",
+        hcl::Value::Number(_) => "a number",
+        hcl::Value::String(_) => "a string",
+        hcl::Value::Array(_) => "a list",
+        hcl::Value::Object(_) => "an object",
+    }
+}
+
+/// `(source, version)` of a `required_providers` entry: either an object with
+/// `source` and `version`, or the legacy bare version string.
+fn provider_from_hcl(
+    expr: &hcl::edit::expr::Expression,
+    string: impl Fn(&hcl::edit::expr::Expression) -> Option<Located>,
+) -> (Option<String>, Option<Located>) {
+    use hcl::edit::expr::{Expression, ObjectKey};
+    match expr {
+        Expression::String(_) => (None, string(expr)),
+        Expression::Object(object) => {
+            let mut source = None;
+            let mut version = None;
+            for (key, value) in object.iter() {
+                let key = match key {
+                    ObjectKey::Ident(ident) => ident.as_str(),
+                    ObjectKey::Expression(Expression::String(s)) => s.as_str(),
+                    _ => continue,
+                };
+                match key {
+                    "source" => source = string(value.expr()).map(|s| s.text),
+                    "version" => version = string(value.expr()),
+                    _ => {}
+                }
+            }
+            (source, version)
+        }
+        _ => (None, None),
+    }
+}
+
+/// The same as `file_info_from_hcl` for a Terraform JSON file, where
+/// `terraform` may be a single block object or an array of block objects.
+fn file_info_from_json(file: &str, content: &str) -> Result<FileInfo> {
+    let root: serde_json::Value = serde_json::from_str(content).map_err(|e| {
+        // serde_json counts lines and columns from 1
+        let offset: usize = content
+            .split_inclusive('\n')
+            .take(e.line().saturating_sub(1))
+            .map(str::len)
+            .sum::<usize>()
+            + e.column().saturating_sub(1);
+        Diagnostic::new(format!("failed to parse JSON in {}", file))
+            .label(Location::new(file, content, offset..offset + 1), e.to_string())
+    })?;
+    let string = |key: &str, value: &str| {
+        let text = unescape_json_template(value);
+        Located {
+            location: json_string_location(file, content, key, value),
+            // Escaped characters make the source longer than the value
+            exact: text == value
+                && serde_json::to_string(value).is_ok_and(|quoted| quoted.len() == value.len() + 2),
+            text,
+        }
+    };
+
+    let mut info = FileInfo::default();
+    let blocks: Vec<&serde_json::Value> = match root.get("terraform") {
+        Some(serde_json::Value::Array(items)) => items.iter().collect(),
+        Some(block) => vec![block],
+        None => Vec::new(),
+    };
+    for block in blocks {
+        match block.get("required_version") {
+            None => {}
+            // JSON strings are templates too, which must not interpolate anything
+            Some(serde_json::Value::String(v)) if !is_json_template(v) => {
+                info.required_versions.push(string("required_version", v))
+            }
+            Some(value) => {
+                let message = match value {
+                    serde_json::Value::String(_) => "interpolations can't be used here".to_string(),
+                    _ => format!("this is {}", value_kind(&hcl::to_value(value)?)),
+                };
+                let diagnostic = Diagnostic::new("required_version must be a literal string");
+                return Err(match json_key_location(file, content, "required_version") {
+                    Some(location) => diagnostic.label(location, message),
+                    None => diagnostic.note(format!("{} in {}", message, file)),
+                }
+                .into());
             }
         }
+        let provider_objects: Vec<&serde_json::Value> = match block.get("required_providers") {
+            Some(serde_json::Value::Array(items)) => items.iter().collect(),
+            Some(obj) => vec![obj],
+            None => Vec::new(),
+        };
+        for (name, entry) in provider_objects.iter().filter_map(|o| o.as_object()).flatten() {
+            let (source, version) = match entry {
+                serde_json::Value::String(version) => (None, Some(string(name, version))),
+                _ => (
+                    entry.get("source").and_then(|v| v.as_str()).map(unescape_json_template),
+                    entry.get("version").and_then(|v| v.as_str()).map(|v| string("version", v)),
+                ),
+            };
+            info.providers.push((name.clone(), source, version));
+        }
     }
+
+    // module blocks are keyed by name: {"module": {"vpc": {"source": ...}}},
+    // again either as one object or an array of them
+    let module_objects: Vec<&serde_json::Value> = match root.get("module") {
+        Some(serde_json::Value::Array(items)) => items.iter().collect(),
+        Some(obj) => vec![obj],
+        None => Vec::new(),
+    };
+    for obj in module_objects {
+        let Some(modules) = obj.as_object() else {
+            continue;
+        };
+        for (name, module) in modules {
+            if let Some(source) = module.get("source").and_then(|v| v.as_str()) {
+                info.modules.push((name.clone(), source.to_string()));
+            }
+        }
+    }
+    Ok(info)
+}
+
+/// Where a JSON string `value` of the key `key` is written in `content`. The
+/// JSON parser keeps no positions, so this finds the first place that matches.
+fn json_string_location(file: &str, content: &str, key: &str, value: &str) -> Option<Location> {
+    let key = serde_json::to_string(key).ok()?;
+    let value = serde_json::to_string(value).ok()?;
+    let pattern = format!(r"{}\s*:\s*{}", regex::escape(&key), regex::escape(&value));
+    let re = Regex::new(&pattern).ok()?;
+    let end = re.find(content)?.end();
+    // Without the quotes
+    Some(Location::new(file, content, end - value.len() + 1..end - 1))
+}
+
+/// Where the key `key` is written in JSON `content`, the first time.
+fn json_key_location(file: &str, content: &str, key: &str) -> Option<Location> {
+    let key = serde_json::to_string(key).ok()?;
+    let start = Regex::new(&format!(r"{}\s*:", regex::escape(&key))).ok()?.find(content)?.start();
+    Some(Location::new(file, content, start..start + key.len()))
+}
+
+/// Whether a string from a Terraform JSON file interpolates something, i.e.
+/// has a `${` or `%{` that isn't escaped by doubling the sign.
+fn is_json_template(s: &str) -> bool {
+    let unescaped = s.replace("$${", "").replace("%%{", "");
+    unescaped.contains("${") || unescaped.contains("%{")
+}
+
+/// The literal text of a Terraform JSON string without interpolations, where
+/// `$${` and `%%{` stand for `${` and `%{`.
+fn unescape_json_template(s: &str) -> String {
+    s.replace("$${", "${").replace("%%{", "%{")
+}
+
+/// Fail, pointing at the offending constraints, if no version can satisfy
+/// every requirement.
+pub fn ensure_satisfiable(constraint: &VersionConstraint) -> Result<()> {
+    if let Some(conflict) = constraint.conflicting() {
+        let mut diagnostic =
+            Diagnostic::new("required_version constraints cannot all be satisfied");
+        for req in conflict {
+            diagnostic = match req.location {
+                Some(ref location) => {
+                    diagnostic.label(location.clone(), format!("allows {}", req.to_set()))
+                }
+                None => diagnostic.note(format!("{} allows {}", req, req.to_set())),
+            };
+        }
+        return Err(diagnostic.into());
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::test_dir::TestDir;
+
     #[test]
     fn test_heredoc_in_list() {
         let input = r#"
@@ -93,5 +949,368 @@ resource "test" "x" {
         assert!(hcl::parse(ok2).is_ok());
     }
 
+    #[test]
+    fn test_required_version_in_json() {
+        let versions = |s| {
+            super::file_info_from_json("main.tf.json", s)
+                .map(|i| i.required_versions.into_iter().map(|v| v.text).collect::<Vec<_>>())
+        };
+        let object = r#"{"terraform": {"required_version": ">= 1.5"}}"#;
+        assert_eq!(versions(object).unwrap(), [">= 1.5"]);
+
+        let array = r#"{
+  "terraform": [
+    {"required_providers": {"aws": {"source": "hashicorp/aws"}}},
+    {"required_version": "~> 1.6"}
+  ],
+  "resource": {}
+}"#;
+        assert_eq!(versions(array).unwrap(), ["~> 1.6"]);
+
+        assert!(versions(r#"{"resource": {}}"#).unwrap().is_empty());
+        assert!(versions("{").is_err());
+    }
+
+    #[test]
+    fn test_diagnostics_point_at_source() {
+        let base = TestDir::new("diagnostics");
+        let write = |name: &str, content: &str| std::fs::write(base.join(name), content).unwrap();
+        let error = || {
+            let err = super::find_required_version(&base, &super::Options::default()).unwrap_err();
+            err.to_string()
+        };
 
+        write("main.tf", "terraform {\n  required_version = \">= 1.3, ~> 1.x\"\n}\n");
+        let invalid = error();
+        write("main.tf", "terraform {\n  required_version = \"~> 1.3.0\"\n}\n");
+        write("versions.tf.json", r#"{"terraform": {"required_version": ">= 1.5"}}"#);
+        let conflict = error();
+
+        assert!(invalid.starts_with("invalid required_version \">= 1.3, ~> 1.x\"\n"));
+        assert!(invalid.contains("main.tf:2:31\n"));
+        assert!(invalid.ends_with("  |                               ^^^^^^ invalid minor version: \
+                                   invalid digit found in string"));
+        assert!(conflict.contains("main.tf:2:23\n"));
+        assert!(conflict.contains("^^^^^^^^ allows [1.3.0, 1.4.0)"));
+        assert!(conflict.contains("versions.tf.json:1:37\n"));
+    }
+
+    #[test]
+    fn test_non_literal_required_version() {
+        let versions = |content: &str| {
+            super::file_info_from_hcl("main.tf", content)
+                .map(|i| i.required_versions.into_iter().map(|v| v.text).collect::<Vec<_>>())
+                .map_err(|e| e.to_string())
+        };
+        let block = |value: &str| format!("terraform {{\n  required_version = {}\n}}\n", value);
+
+        // Templates and heredocs that don't interpolate anything are plain strings
+        assert_eq!(versions(&block(r#""$${v} >= 1.5""#)).unwrap(), ["${v} >= 1.5"]);
+        assert_eq!(versions(&block("<<-EOT\n    >= 1.5\n  EOT")).unwrap(), [">= 1.5\n"]);
+        let heredoc = super::file_info_from_hcl("main.tf", &block("<<EOT\n>= 1.x\nEOT")).unwrap();
+        let err = super::parse_constraint(&heredoc.required_versions[0], "required_version", "x")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("  |                      ^^^^^ invalid minor version"), "{}", err);
+
+        let err = versions(&block("var.terraform_version")).unwrap_err();
+        assert!(err.starts_with("required_version must be a literal string\n"));
+        assert!(err.contains(" --> main.tf:2:22\n"));
+        assert!(err.contains("^^^^^^^^^^^^^^^^^^^^^ references, function calls"));
+        assert!(versions(&block(r#"">= ${local.min}""#)).is_err());
+
+        let err = versions(&block("1.5")).unwrap_err();
+        assert!(err.contains("^^^ this is a number"));
+        assert!(err.ends_with("= note: quote it: \"1.5\""));
+
+        let json = |content: &str| super::file_info_from_json("main.tf.json", content).map(|_| ());
+        assert!(json(r#"{"terraform": {"required_version": "$${x}"}}"#).is_ok());
+        let info = super::file_info_from_json(
+            "main.tf.json",
+            r#"{"terraform": {"required_version": "$${v} %%{x} >= 1.5"}}"#,
+        )
+        .unwrap();
+        assert_eq!(info.required_versions[0].text, "${v} %{x} >= 1.5");
+        assert!(!info.required_versions[0].exact);
+        let err = json(r#"{"terraform": {"required_version": ">= ${var.v}"}}"#).unwrap_err();
+        assert!(err.to_string().contains("^^^^^^^^^^^^^^^^^^ interpolations can't be used here"));
+        let err = json(r#"{"terraform": {"required_version": 1.5}}"#).unwrap_err();
+        assert!(err.to_string().contains("this is a number"));
+    }
+
+    #[test]
+    fn test_required_providers() {
+        use crate::constraint::Version;
+
+        let base = TestDir::new("providers");
+        std::fs::create_dir_all(base.join("network")).unwrap();
+        std::fs::write(
+            base.join("main.tf"),
+            r#"
+terraform {
+  required_providers {
+    aws = {
+      source  = "hashicorp/aws"
+      version = "~> 5.0"
+    }
+    cf = {
+      source = "registry.terraform.io/Cloudflare/cloudflare"
+    }
+    random = ">= 3.0"
+  }
+}
+
+module "network" {
+  source = "./network"
+}
+"#,
+        )
+        .unwrap();
+        std::fs::write(
+            base.join("network/main.tf.json"),
+            r#"{"terraform": {"required_providers": {"aws": {"version": ">= 5.10"}}}}"#,
+        )
+        .unwrap();
+
+        let providers = super::find_required_providers(&base, &super::Options::default()).unwrap();
+
+        let sources: Vec<&str> = providers.iter().map(|p| p.source.as_str()).collect();
+        assert_eq!(sources, ["hashicorp/aws", "cloudflare/cloudflare", "hashicorp/random"]);
+
+        // The module's constraint on aws narrows the root's
+        let aws = providers[0].constraint.as_ref().unwrap();
+        assert!(aws.matches(&Version::new(5, 31, 0)));
+        assert!(!aws.matches(&Version::new(5, 9, 0)));
+        assert!(providers[1].constraint.is_none());
+        assert!(providers[2].constraint.as_ref().unwrap().matches(&Version::new(3, 6, 0)));
+    }
+
+    #[test]
+    fn test_locked_providers() {
+        let base = TestDir::new("lock");
+        assert!(super::locked_providers(&base).is_err());
+
+        std::fs::write(
+            base.join(".terraform.lock.hcl"),
+            r#"# This file is maintained automatically by "terraform init".
+provider "registry.terraform.io/hashicorp/aws" {
+  version     = "5.31.0"
+  constraints = "~> 5.0"
+  hashes = [
+    "h1:abc=",
+  ]
+}
+
+provider "registry.terraform.io/cloudflare/cloudflare" {
+  version = "4.20.0"
+}
+"#,
+        )
+        .unwrap();
+        let locked = super::locked_providers(&base).unwrap();
+
+        assert_eq!(locked.len(), 2);
+        assert_eq!(locked[0].source, "hashicorp/aws");
+        assert_eq!(locked[0].constraint.as_ref().unwrap().to_string(), "= 5.31.0");
+        assert_eq!(locked[1].source, "cloudflare/cloudflare");
+    }
+
+    #[test]
+    fn test_terragrunt_includes() {
+        let base = TestDir::new("terragrunt");
+        std::fs::create_dir_all(base.join("live/prod/app")).unwrap();
+        std::fs::write(
+            base.join("live/root.hcl"),
+            r#"
+terraform_version_constraint  = ">= 1.5"
+terragrunt_version_constraint = ">= 0.50"
+"#,
+        )
+        .unwrap();
+        std::fs::write(
+            base.join("live/prod/env.hcl"),
+            "terraform_version_constraint = \"~> 1.6.0\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            base.join("live/prod/app/terragrunt.hcl"),
+            r#"
+include "root" {
+  path = find_in_parent_folders("root.hcl")
+}
+
+include "env" {
+  path = "../env.hcl"
+}
+
+terraform {
+  source = "../../../modules/app"
+}
+"#,
+        )
+        .unwrap();
+
+        let app = super::find_terragrunt_constraints(&base.join("live/prod/app"));
+        let none = super::find_terragrunt_constraints(&base.join("live"));
+
+        // The later include overrides the earlier one, as in Terragrunt
+        let app = app.unwrap().unwrap();
+        assert_eq!(app.terraform.unwrap().to_string(), "~> 1.6.0");
+        assert_eq!(app.terragrunt.unwrap().to_string(), ">= 0.50");
+        assert!(none.unwrap().is_none());
+
+        // Constraints Terragrunt would have to evaluate are reported
+        let computed = "terraform_version_constraint = local.terraform\n";
+        std::fs::write(base.join("live/terragrunt.hcl"), computed).unwrap();
+        let err = super::find_terragrunt_constraints(&base.join("live")).unwrap_err().to_string();
+        assert!(err.starts_with("terraform_version_constraint must be a literal string\n"));
+        assert!(err.contains("terragrunt.hcl:1:32\n"), "{}", err);
+    }
+
+    #[test]
+    fn test_tofu_files_take_precedence() {
+        let base = TestDir::new("tofu");
+        let write = |name: &str, version: &str| {
+            let content = format!("terraform {{\n  required_version = \"{}\"\n}}\n", version);
+            std::fs::write(base.join(name), content).unwrap();
+        };
+        write("versions.tf", ">= 1.5");
+        write("versions.tofu", ">= 1.6");
+        write("main.tf", "< 2.0");
+
+        let read = |engine| {
+            let options = super::Options {
+                engine,
+                ..Default::default()
+            };
+            super::find_required_version(&base, &options).unwrap().unwrap().to_string()
+        };
+        let terraform = read(super::Engine::Terraform);
+        let tofu = read(super::Engine::OpenTofu);
+
+        // Terraform ignores .tofu files; OpenTofu reads versions.tofu instead of versions.tf
+        assert_eq!(terraform, "< 2.0, >= 1.5");
+        assert_eq!(tofu, "< 2.0, >= 1.6");
+    }
+
+    #[test]
+    fn test_override_files() {
+        let base = TestDir::new("override");
+        let write = |name: &str, content: &str| std::fs::write(base.join(name), content).unwrap();
+        write(
+            "versions.tf",
+            r#"
+terraform {
+  required_version = "~> 1.3.0"
+  required_providers {
+    aws    = { source = "hashicorp/aws", version = "~> 4.0" }
+    random = { source = "hashicorp/random", version = "~> 3.0" }
+  }
+}
+"#,
+        );
+        write("main.tf", "terraform {\n  required_version = \">= 1.2\"\n}\n");
+        write(
+            "z_override.tf",
+            "terraform {
+  required_providers {
+    aws = { version = \"~> 5.0\" }
+  }
+}
+",
+        );
+        write("override.tf", "terraform {
+  required_version = \">= 1.5\"
+}
+");
+        write("a_override.tf.json", r#"{"terraform": {"required_version": ">= 1.6"}}"#);
+
+        let options = super::Options::default();
+        let version = super::find_required_version(&base, &options);
+        let providers = super::find_required_providers(&base, &options);
+
+        // override.tf comes after a_override.tf.json, replacing it and the base versions
+        let version = version.unwrap().unwrap();
+        assert_eq!(version.to_string(), ">= 1.5");
+        assert!(version.requirements()[0].source.as_deref().unwrap().ends_with("/override.tf"));
+
+        // Only the aws entry is overridden, and loses its source address with it
+        let providers: Vec<String> = providers
+            .unwrap()
+            .iter()
+            .map(|p| format!("{} {}", p.source, p.constraint.as_ref().unwrap()))
+            .collect();
+        assert_eq!(providers, ["hashicorp/random ~> 3.0", "hashicorp/aws ~> 5.0"]);
+    }
+
+    #[test]
+    fn test_follows_local_modules() {
+        let base = TestDir::new("modules");
+        let write = |path: &str, content: &str| {
+            let path = base.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        };
+        write(
+            "root/main.tf",
+            r#"
+terraform {
+  required_version = ">= 1.3"
+}
+module "net" {
+  source = "../modules/net"
+}
+module "remote" {
+  source = "hashicorp/consul/aws"
+}
+"#,
+        );
+        write(
+            "modules/net/main.tf",
+            r#"
+terraform {
+  required_version = ">= 1.5"
+}
+module "back" {
+  source = "../net"
+}
+"#,
+        );
+
+        let options = super::Options::default();
+        let c = super::find_required_version(&base.join("root"), &options).unwrap().unwrap();
+        assert_eq!(c.to_string(), ">= 1.3, >= 1.5");
+        let child = &c.requirements()[1];
+        assert!(child.source.as_deref().unwrap().ends_with("main.tf (module.net)"));
+        assert_eq!(
+            super::local_module_dirs(&base.join("root"), super::Engine::Terraform).unwrap(),
+            [base.join("modules/net").canonicalize().unwrap()]
+        );
+
+        // Downloaded modules only count when asked for
+        write(
+            "root/.terraform/modules/modules.json",
+            r#"{"Modules":[{"Key":"","Source":"","Dir":"."},{"Key":"remote","Source":"registry.terraform.io/hashicorp/consul/aws","Version":"0.1.0","Dir":".terraform/modules/remote"}]}"#,
+        );
+        write(
+            "root/.terraform/modules/remote/versions.tf",
+            "terraform {\n  required_version = \">= 1.6\"\n}\n",
+        );
+        let c = super::find_required_version(&base.join("root"), &options).unwrap().unwrap();
+        assert_eq!(c.to_string(), ">= 1.3, >= 1.5");
+        assert_eq!(super::missing_modules_manifest(&base.join("root"), &options), None);
+        let options = super::Options {
+            vendored_modules: true,
+            ..Default::default()
+        };
+        let c = super::find_required_version(&base.join("root"), &options).unwrap().unwrap();
+        assert_eq!(c.to_string(), ">= 1.3, >= 1.5, >= 1.6");
+        assert!(c.requirements()[2].source.as_deref().unwrap().ends_with("(module.remote)"));
+        assert_eq!(super::missing_modules_manifest(&base.join("root"), &options), None);
+        let net = base.join("modules/net");
+        let missing = super::missing_modules_manifest(&net, &options);
+        assert_eq!(missing, Some(net.join(".terraform/modules/modules.json")));
+        let c = super::find_required_version(&net, &options).unwrap().unwrap();
+        assert_eq!(c.to_string(), ">= 1.5");
+    }
 }
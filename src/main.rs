@@ -1,18 +1,37 @@
+mod cache;
 mod constraint;
+mod diagnostic;
+mod discover;
+mod engine;
+mod explain;
 mod flake_check;
 mod flake_generate;
 mod flake_update;
+mod git_repo;
 mod hcl;
 mod nixpkgs;
+mod parallel;
+mod providers;
+#[cfg(test)]
+mod test_dir;
+mod version_files;
+mod version_set;
 
-use anyhow::{Context, Result};
-use clap::Parser;
-use std::path::PathBuf;
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use constraint::VersionConstraint;
+use engine::Engine;
+use version_files::VersionFile;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser, Debug)]
 #[command(name = "tfg")]
-#[command(about = "Generate Nix flakes from Terraform version constraints")]
+#[command(about = "Generate Nix flakes from Terraform or OpenTofu version constraints")]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Terraform version to pin (e.g., 1.5.0)
     #[arg(value_name = "VERSION")]
     version: Option<String>,
@@ -22,37 +41,828 @@ struct Args {
     version_flag: Option<String>,
 
     /// Working directory containing .tf files
-    #[arg(long, default_value = ".")]
+    #[arg(long, default_value = ".", global = true)]
     dir: PathBuf,
 
+    /// Search --dir recursively for Terraform root modules
+    #[arg(short, long, global = true)]
+    recursive: bool,
+
+    /// With --recursive, only use root modules whose path matches this glob (repeatable)
+    #[arg(long, value_name = "GLOB", global = true)]
+    include: Vec<String>,
+
+    /// With --recursive, skip directories whose path matches this glob (repeatable)
+    #[arg(long, value_name = "GLOB", global = true)]
+    exclude: Vec<String>,
+
+    /// With --recursive, generate one devShell per root module, each pinned to
+    /// a Terraform satisfying that root's own constraint
+    #[arg(long, requires = "recursive", conflicts_with_all = ["version", "version_flag"])]
+    per_root: bool,
+
+    /// Also read required_version from modules downloaded by `terraform init`
+    /// (listed in .terraform/modules/modules.json)
+    #[arg(long, global = true)]
+    vendored_modules: bool,
+
+    /// Require the provider versions pinned in .terraform.lock.hcl to be
+    /// packaged at the chosen nixpkgs commit, searching for one where they are
+    #[arg(long, global = true)]
+    locked_providers: bool,
+
+    /// Which Terraform implementation to pin
+    #[arg(long, value_enum, default_value_t = EngineArg::Terraform, global = true)]
+    engine: EngineArg,
+
     /// GitHub token for API access (or set GITHUB_TOKEN env var)
-    #[arg(long, env = "GITHUB_TOKEN")]
+    #[arg(long, env = "GITHUB_TOKEN", global = true)]
     github_token: Option<String>,
 
+    /// Read nixpkgs from a local git clone instead of GitHub
+    #[arg(long, value_name = "PATH", global = true)]
+    nixpkgs_repo: Option<PathBuf>,
+
+    /// Answer nixpkgs lookups from the cache only, without network access
+    #[arg(long, global = true)]
+    offline: bool,
+
+    /// When GitHub's rate limit is hit, wait for it to reset instead of failing
+    #[arg(long, global = true)]
+    wait_for_rate_limit: bool,
+
+    /// Walk at most this many commits of the package's history
+    #[arg(long, value_name = "N", global = true)]
+    history_depth: Option<usize>,
+
+    /// Don't walk the package's history back past this date (YYYY-MM-DD)
+    #[arg(long, value_name = "DATE", value_parser = parse_date, global = true)]
+    history_since: Option<String>,
+
+    /// How many nixpkgs lookups to run at once
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 8,
+        value_parser = clap::value_parser!(u32).range(1..),
+        global = true
+    )]
+    jobs: u32,
+
+    /// Where to read the Terraform constraint from, highest precedence first
+    /// (comma-separated); sources left out are ignored
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        global = true,
+        default_values_t = [
+            SourceArg::RequiredVersion,
+            SourceArg::TerraformVersion,
+            SourceArg::ToolVersions,
+            SourceArg::Mise,
+        ]
+    )]
+    constraint_sources: Vec<SourceArg>,
+
+    /// How to choose among Terraform versions that satisfy the constraint
+    /// [default: highest, or what a version file such as `min-required` implies]
+    #[arg(long, value_enum)]
+    strategy: Option<StrategyArg>,
+
     /// Show detailed search progress
     #[arg(short, long)]
     verbose: bool,
 }
 
+impl Args {
+    fn engine(&self) -> Engine {
+        match self.engine {
+            EngineArg::Terraform => Engine::Terraform,
+            EngineArg::Opentofu => Engine::OpenTofu,
+        }
+    }
+
+    fn hcl_options(&self) -> hcl::Options {
+        hcl::Options {
+            vendored_modules: self.vendored_modules,
+            engine: self.engine(),
+        }
+    }
+
+    fn find_roots(&self, dir: &Path) -> Result<Vec<discover::Root>> {
+        discover::find_roots(dir, &self.include, &self.exclude, self.engine())
+    }
+
+    fn search_options(&self) -> nixpkgs::SearchOptions {
+        nixpkgs::SearchOptions {
+            history: nixpkgs::HistoryLimit {
+                depth: self.history_depth,
+                since: self.history_since.clone(),
+            },
+            jobs: self.jobs as usize,
+        }
+    }
+
+    fn nixpkgs_source(&self) -> Result<Box<dyn nixpkgs::NixpkgsSource>> {
+        Ok(match &self.nixpkgs_repo {
+            Some(path) => Box::new(git_repo::GitRepo::open(path)?),
+            None => {
+                let cache = cache::Cache::default_path().map(|p| cache::Cache::open(&p));
+                if self.offline && cache.is_none() {
+                    bail!("--offline needs a cache directory; set XDG_CACHE_HOME or HOME");
+                }
+                let token = self.github_token.as_deref();
+                Box::new(nixpkgs::GitHub::new(
+                    token,
+                    cache,
+                    self.offline,
+                    self.wait_for_rate_limit,
+                )?)
+            }
+        })
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show how each comparator of a constraint judges a version
+    Explain {
+        /// Version to evaluate (defaults to the Terraform pinned by flake.nix)
+        #[arg(value_name = "VERSION")]
+        version: Option<String>,
+
+        /// Constraint to evaluate (defaults to required_version from the .tf files)
+        #[arg(long)]
+        constraint: Option<String>,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum EngineArg {
+    /// HashiCorp Terraform, from nixpkgs' `terraform`
+    Terraform,
+    /// OpenTofu, from nixpkgs' `opentofu`; also reads .tofu files
+    Opentofu,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum StrategyArg {
+    /// The newest matching version
+    Highest,
+    /// The oldest matching version
+    Lowest,
+    /// The version closest to the `terraform` (or `tofu`) binary on PATH
+    Closest,
+    /// The newest version on a stable nixos-YY.MM branch, falling back to unstable
+    PreferStable,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum SourceArg {
+    /// required_version in the .tf and .tf.json files
+    RequiredVersion,
+    /// .terraform-version, as read by tfenv (.opentofu-version with --engine opentofu)
+    TerraformVersion,
+    /// .tool-versions, as read by asdf
+    ToolVersions,
+    /// mise.toml or .mise.toml
+    Mise,
+}
+
+/// Check a `YYYY-MM-DD` date argument, which is compared against commit dates as text.
+fn parse_date(s: &str) -> Result<String, String> {
+    let valid = s.len() == 10
+        && s.char_indices().all(|(i, c)| match i {
+            4 | 7 => c == '-',
+            _ => c.is_ascii_digit(),
+        });
+    if !valid {
+        return Err(format!("expected a date as YYYY-MM-DD, got {}", s));
+    }
+    Ok(s.to_string())
+}
+
+/// The Terraform constraint of the module at `dir`: its `required_version`
+/// together with the `terraform_version_constraint` of its `terragrunt.hcl`.
+fn module_constraint(dir: &Path, args: &Args) -> Result<Option<VersionConstraint>> {
+    let options = args.hcl_options();
+    if let Some(manifest) = hcl::missing_modules_manifest(dir, &options) {
+        eprintln!(
+            "Warning: {} not found; run `terraform init` to include downloaded modules",
+            manifest.display()
+        );
+    }
+    let required_version = hcl::find_required_version(dir, &options)?;
+    let terragrunt = hcl::find_terragrunt_constraints(dir)?.and_then(|t| t.terraform);
+    let combined = match (required_version, terragrunt) {
+        (Some(a), Some(b)) => Some(a.intersect(b)),
+        (a, b) => a.or(b),
+    };
+    if let Some(ref c) = combined {
+        hcl::ensure_satisfiable(c)?;
+    }
+    Ok(combined)
+}
+
+/// The Terragrunt configuration of `dir`, or of every root module under it
+/// with --recursive, merged. `None` if Terragrunt isn't used.
+fn project_terragrunt(dir: &Path, args: &Args) -> Result<Option<hcl::TerragruntConstraints>> {
+    if !args.recursive {
+        return hcl::find_terragrunt_constraints(dir);
+    }
+    let mut merged: Option<hcl::TerragruntConstraints> = None;
+    for root in args.find_roots(dir)? {
+        if let Some(t) = hcl::find_terragrunt_constraints(&root.path)? {
+            merged = Some(match merged {
+                Some(m) => m.merge(t),
+                None => t,
+            });
+        }
+    }
+    Ok(merged)
+}
+
+/// Packages to put next to the engine in the dev shell of the module at `dir`.
+fn shell_packages(dir: &Path) -> Vec<String> {
+    if dir.join("terragrunt.hcl").is_file() {
+        vec!["terragrunt".to_string()]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Discover every root module under `dir` along with its own constraint, if any.
+fn root_constraints(
+    dir: &Path,
+    args: &Args,
+) -> Result<Vec<(discover::Root, Option<VersionConstraint>)>> {
+    let roots = args.find_roots(dir)?;
+    if roots.is_empty() {
+        bail!("no {} root modules found under {}", args.engine(), dir.display());
+    }
+
+    println!("Found {} root modules:", roots.len());
+    let mut result = Vec::with_capacity(roots.len());
+    for root in roots {
+        let constraint = module_constraint(&root.path, args)?;
+        match constraint {
+            Some(ref c) => println!("  {}: {}", root.name, c),
+            None => println!("  {}: no required_version", root.name),
+        }
+        result.push((root, constraint));
+    }
+    Ok(result)
+}
+
+/// Intersect the constraints of all roots that have one.
+fn combine(roots: &[(discover::Root, Option<VersionConstraint>)]) -> Option<VersionConstraint> {
+    roots
+        .iter()
+        .filter_map(|(_, c)| c.clone())
+        .reduce(|acc, c| acc.intersect(c))
+}
+
+/// Discover every root module under `dir` and combine their constraints into
+/// one that holds for the whole repository. `None` if no root has one.
+fn repo_constraint(dir: &Path, args: &Args) -> Result<Option<VersionConstraint>> {
+    let roots = root_constraints(dir, args)?;
+    let combined = combine(&roots);
+    if let Some(ref c) = combined {
+        hcl::ensure_satisfiable(c)?;
+    }
+    Ok(combined)
+}
+
+/// The constraint for `dir`: the first of --constraint-sources that has one,
+/// along with the pick a version file implies, if any. Every version file is
+/// checked against required_version, with a warning if they disagree; those
+/// after the chosen source only warn if they can't be read.
+fn project_constraint(
+    dir: &Path,
+    args: &Args,
+) -> Result<(VersionConstraint, Option<constraint::Strategy>)> {
+    let required_version = if args.recursive {
+        repo_constraint(dir, args)?
+    } else {
+        module_constraint(dir, args)?
+    };
+
+    let mut chosen = None;
+    for source in &args.constraint_sources {
+        let file = match source {
+            SourceArg::RequiredVersion => {
+                if chosen.is_none() {
+                    chosen = required_version.clone().map(|c| (c, None));
+                }
+                continue;
+            }
+            SourceArg::TerraformVersion => VersionFile::TerraformVersion,
+            SourceArg::ToolVersions => VersionFile::ToolVersions,
+            SourceArg::Mise => VersionFile::Mise,
+        };
+        let pin = match version_files::read(dir, file, args.engine()) {
+            Ok(Some(p)) => p,
+            Ok(None) => continue,
+            // Only the source that decides has to be understood
+            Err(e) if chosen.is_some() => {
+                eprintln!("Warning: ignoring {:#}", e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if let (Some(pinned), Some(required)) = (&pin.constraint, &required_version) {
+            warn_if_disagrees(&pin.file, pinned, required);
+        }
+        if chosen.is_some() {
+            continue;
+        }
+
+        // Forms like `latest` and `min-required` pick from required_version
+        let constraint = match (pin.constraint, &required_version) {
+            (Some(c), _) => c,
+            (None, Some(required)) => required.clone(),
+            (None, None) if pin.strategy == Some(constraint::Strategy::Lowest) => bail!(
+                "{} asks for the minimum required version, but there is no required_version",
+                pin.file
+            ),
+            (None, None) => VersionConstraint::parse(">= 0.0.0")?.with_source(&pin.file),
+        };
+        chosen = Some((constraint, pin.strategy));
+    }
+
+    chosen.with_context(|| {
+        let engine = args.engine();
+        let version_file = match engine {
+            Engine::Terraform => ".terraform-version",
+            Engine::OpenTofu => ".opentofu-version",
+        };
+        format!(
+            "no required_version found in any {} files in {}, \
+             and no {} version in {}, .tool-versions or mise.toml",
+            engine.config_extensions().join(" or "),
+            dir.display(),
+            engine,
+            version_file
+        )
+    })
+}
+
+fn warn_if_disagrees(file: &str, pinned: &VersionConstraint, required: &VersionConstraint) {
+    let pinned_set = pinned.to_set();
+    let required_set = required.to_set();
+    if pinned_set.intersect(&required_set).is_empty() {
+        eprintln!(
+            "Warning: {} asks for \"{}\", which required_version \"{}\" rejects",
+            file, pinned, required
+        );
+    } else if !pinned_set.is_subset(&required_set) {
+        eprintln!(
+            "Warning: {} allows \"{}\", which required_version \"{}\" only partly allows",
+            file, pinned, required
+        );
+    }
+}
+
+/// The providers the module at `dir` needs: its `required_providers`, or with
+/// --locked-providers the exact versions in its .terraform.lock.hcl.
+fn module_providers(dir: &Path, args: &Args) -> Result<Vec<hcl::ProviderRequirement>> {
+    if args.locked_providers {
+        hcl::locked_providers(dir)
+    } else {
+        hcl::find_required_providers(dir, &args.hcl_options())
+    }
+}
+
+/// `module_providers` of `dir`, or of every root module under it with --recursive.
+fn required_providers(dir: &Path, args: &Args) -> Result<Vec<hcl::ProviderRequirement>> {
+    if !args.recursive {
+        return module_providers(dir, args);
+    }
+    let mut required = Vec::new();
+    for root in args.find_roots(dir)? {
+        required.extend(module_providers(&root.path, args)?);
+    }
+    Ok(required)
+}
+
+/// The providers packaged at a nixpkgs commit. Not being able to list them is
+/// an error, rather than a flake that quietly bundles none.
+fn packaged_providers(
+    commit: &str,
+    source: &dyn nixpkgs::NixpkgsSource,
+) -> Result<Vec<nixpkgs::PackagedProvider>> {
+    nixpkgs::providers_at_commit(commit, source)
+        .context("could not list terraform providers in nixpkgs")
+}
+
+/// `packaged_providers`, listing each commit only once.
+fn packaged_at<'a>(
+    cache: &'a mut HashMap<String, Vec<nixpkgs::PackagedProvider>>,
+    commit: &str,
+    source: &dyn nixpkgs::NixpkgsSource,
+) -> Result<&'a [nixpkgs::PackagedProvider]> {
+    if !cache.contains_key(commit) {
+        let packaged = packaged_providers(commit, source)?;
+        cache.insert(commit.to_string(), packaged);
+    }
+    Ok(&cache[commit])
+}
+
+/// Report how the providers resolved, warning about those that
+/// `terraform init` will still have to download, and return the plugins.
+fn provider_plugins(resolution: providers::Resolution) -> Vec<String> {
+    for unmatched in &resolution.unmatched {
+        eprintln!("Warning: provider {} is not bundled", unmatched);
+    }
+    if !resolution.plugins.is_empty() {
+        println!("Bundling providers: {}", resolution.plugins.join(", "));
+    }
+    resolution.plugins
+}
+
+/// With the existing flake's engine already matching, check the rest of
+/// what the project needs at its pinned commit and add providers and packages
+/// to the shell. Returns `false` if the pinned commit falls short and another
+/// one must be searched for.
+fn complete_existing_flake(
+    dir: &Path,
+    args: &Args,
+    source: &dyn nixpkgs::NixpkgsSource,
+    required: &[hcl::ProviderRequirement],
+    terragrunt: Option<&VersionConstraint>,
+    packages: &[String],
+) -> Result<bool> {
+    if required.is_empty() && packages.is_empty() {
+        return Ok(true);
+    }
+    let commit = match flake_check::pinned_commit(dir)? {
+        Some(c) => c,
+        None => return Ok(true),
+    };
+
+    if let Some(wanted) = terragrunt {
+        match nixpkgs::terragrunt_version_at_commit(&commit, source)? {
+            Some(v) => {
+                let version = constraint::Version::parse(&v)?;
+                if !wanted.matches(&version) {
+                    println!("Existing flake.nix has Terragrunt {} (not a match)", version);
+                    return Ok(false);
+                }
+            }
+            None => {
+                println!("Could not determine Terragrunt version in existing flake.nix");
+                return Ok(false);
+            }
+        }
+    }
+
+    let resolution = if required.is_empty() {
+        providers::Resolution::default()
+    } else {
+        providers::resolve(required, &packaged_providers(&commit, source)?)
+    };
+    if args.locked_providers && !resolution.unmatched.is_empty() {
+        println!("Its providers do not match .terraform.lock.hcl");
+        return Ok(false);
+    }
+    let plugins = provider_plugins(resolution);
+    if flake_update::update_shell(dir, args.engine(), &plugins, packages)? {
+        println!("Updated flake.nix");
+    }
+    Ok(true)
+}
+
+/// Write one flake at `dir` with a devShell per root module, each pinned to a
+/// nixpkgs commit whose engine satisfies that root's constraint. Roots share
+/// a nixpkgs input whenever one commit satisfies them all, so extra inputs
+/// only appear where roots genuinely need different versions.
+fn generate_per_root(
+    dir: &Path,
+    args: &Args,
+    strategy: &constraint::Strategy,
+    verbose: bool,
+) -> Result<()> {
+    if dir.join("flake.nix").exists() {
+        bail!(
+            "flake.nix already exists in {}; remove it to generate per-root shells",
+            dir.display()
+        );
+    }
+
+    let source = args.nixpkgs_source()?;
+    let engine = args.engine();
+    let roots = root_constraints(dir, args)?;
+    if roots.iter().all(|(_, c)| c.is_none()) {
+        bail!("no required_version found in any root module under {}", dir.display());
+    }
+    let root_names: Vec<&str> = roots.iter().map(|(root, _)| root.name.as_str()).collect();
+    let shell_names = shell_names(&root_names)?;
+    let required = roots
+        .iter()
+        .map(|(root, _)| module_providers(&root.path, args))
+        .collect::<Result<Vec<_>>>()?;
+    // Providers only steer the search with --locked-providers
+    let search_providers = |providers: &[hcl::ProviderRequirement]| {
+        if args.locked_providers {
+            providers.to_vec()
+        } else {
+            Vec::new()
+        }
+    };
+    // Terragrunt's own constraint usually comes from one shared root config,
+    // so every pin has to satisfy all of them
+    let terragrunt = project_terragrunt(dir, args)?.and_then(|t| t.terragrunt);
+    let find = |constraint: &VersionConstraint,
+                providers: Vec<hcl::ProviderRequirement>|
+     -> Result<(constraint::Version, String)> {
+        println!("Searching nixpkgs for {} satisfying \"{}\"...", engine, constraint);
+        let requirements = nixpkgs::Requirements {
+            providers: &providers,
+            terragrunt: terragrunt.as_ref(),
+        };
+        let found = nixpkgs::find_engine_commit(
+            constraint,
+            engine,
+            &requirements,
+            strategy,
+            &args.search_options(),
+            &*source,
+            verbose,
+        )
+        .with_context(|| {
+            format!("no {} version satisfying \"{}\" found in nixpkgs", engine, constraint)
+        })?;
+        let commit = nixpkgs::short_sha(&found.commit);
+        println!("Found {} {} at nixpkgs {}", engine, found.version, commit);
+        Ok((found.version, found.commit))
+    };
+
+    // One (version, commit) per nixpkgs input; try a single shared pin first
+    let mut pins: Vec<(constraint::Version, String)> = Vec::new();
+    if let Some(combined) = combine(&roots) {
+        if combined.conflicting().is_none() {
+            pins.push(find(&combined, search_providers(&required.concat()))?);
+        }
+    }
+
+    // Index into `pins` for each root; unconstrained roots use the first pin
+    let mut packaged = HashMap::new();
+    let mut assignments = Vec::with_capacity(roots.len());
+    for ((root, constraint), required) in roots.iter().zip(&required) {
+        let pin = match constraint {
+            None => 0,
+            Some(c) => {
+                let mut fits = None;
+                for (i, (v, commit)) in pins.iter().enumerate() {
+                    if !c.matches(v) {
+                        continue;
+                    }
+                    if args.locked_providers {
+                        let available = packaged_at(&mut packaged, commit, &*source)?;
+                        if !providers::resolve(required, available).unmatched.is_empty() {
+                            continue;
+                        }
+                    }
+                    fits = Some(i);
+                    break;
+                }
+                match fits {
+                    Some(i) => i,
+                    None => {
+                        pins.push(find(c, search_providers(required))?);
+                        pins.len() - 1
+                    }
+                }
+            }
+        };
+        assignments.push((root, required, pin));
+    }
+
+    let names = input_names(&pins);
+    let input_name = |pin: usize| names[pin].clone();
+    let inputs: Vec<flake_generate::Input> = pins
+        .iter()
+        .enumerate()
+        .map(|(i, (_, commit))| flake_generate::Input {
+            name: input_name(i),
+            commit: commit.clone(),
+        })
+        .collect();
+
+    // Each root's providers are resolved against its own pin
+    let mut shells = vec![flake_generate::Shell {
+        name: "default".to_string(),
+        input: input_name(0),
+        plugins: Vec::new(),
+        packages: Vec::new(),
+    }];
+    for ((root, required, pin), shell_name) in assignments.into_iter().zip(shell_names) {
+        println!("  {} -> {} {}", root.name, engine, pins[pin].0);
+        let plugins = if required.is_empty() {
+            Vec::new()
+        } else {
+            let available = packaged_at(&mut packaged, &pins[pin].1, &*source)?;
+            provider_plugins(providers::resolve(required, available))
+        };
+
+        // The default shell follows the root at `dir` itself, if there is one
+        if root.name == "." {
+            shells[0].input = input_name(pin);
+            shells[0].plugins = plugins;
+            shells[0].packages = shell_packages(&root.path);
+        } else {
+            shells.push(flake_generate::Shell {
+                name: shell_name,
+                input: input_name(pin),
+                plugins,
+                packages: shell_packages(&root.path),
+            });
+        }
+    }
+
+    flake_generate::generate_shells(dir, engine, &inputs, &shells)?;
+    println!(
+        "Generated flake.nix with {} devShells and {} nixpkgs inputs",
+        shells.len(),
+        inputs.len()
+    );
+    Ok(())
+}
+
+/// The devShell for each root: `default` for the root at --dir itself, and
+/// the root's path with `/` turned into `-` for the others. Two roots ending
+/// up with the same shell, including the default one, is an error.
+fn shell_names(roots: &[&str]) -> Result<Vec<String>> {
+    let mut taken: HashMap<String, &str> = HashMap::new();
+    roots
+        .iter()
+        .map(|&root| {
+            if root == "." {
+                return Ok("default".to_string());
+            }
+            let name = root.replace('/', "-");
+            if name == "default" {
+                bail!(
+                    "root {} would get devShell \"default\", which is the flake's default \
+                     shell; leave it out with --exclude",
+                    root
+                );
+            }
+            if let Some(other) = taken.insert(name.clone(), root) {
+                bail!(
+                    "roots {} and {} would both get devShell \"{}\"; leave one out with --exclude",
+                    other,
+                    root,
+                    name
+                );
+            }
+            Ok(name)
+        })
+        .collect()
+}
+
+/// The flake input for each pin: `nixpkgs` for the first, `nixpkgs-<version>`
+/// for the others. With --locked-providers two pins can have the same version
+/// at different commits, so those get the start of their commit added.
+fn input_names(pins: &[(constraint::Version, String)]) -> Vec<String> {
+    let version = |pin: &(constraint::Version, String)| pin.0.to_string().replace(['.', '+'], "_");
+    pins.iter()
+        .enumerate()
+        .map(|(i, pin)| {
+            if i == 0 {
+                return "nixpkgs".to_string();
+            }
+            let name = format!("nixpkgs-{}", version(pin));
+            if pins[1..].iter().filter(|p| version(p) == version(pin)).count() > 1 {
+                format!("{}-{}", name, pin.1.get(..7).unwrap_or(&pin.1))
+            } else {
+                name
+            }
+        })
+        .collect()
+}
+
+fn resolve_strategy(
+    arg: StrategyArg,
+    engine: Engine,
+    verbose: bool,
+) -> Result<constraint::Strategy> {
+    Ok(match arg {
+        StrategyArg::Highest => constraint::Strategy::Highest,
+        StrategyArg::Lowest => constraint::Strategy::Lowest,
+        StrategyArg::PreferStable => constraint::Strategy::PreferStable,
+        StrategyArg::Closest => match installed_version(engine)? {
+            Some(installed) => {
+                if verbose {
+                    println!("Preferring versions close to installed {} {}", engine, installed);
+                }
+                constraint::Strategy::Closest(installed)
+            }
+            None => {
+                eprintln!(
+                    "Warning: no {} on PATH, falling back to --strategy highest",
+                    engine.binary()
+                );
+                constraint::Strategy::Highest
+            }
+        },
+    })
+}
+
+/// Ask the engine's binary on PATH for its version, if there is one.
+/// `tofu version -json` reports it under `terraform_version` too.
+fn installed_version(engine: Engine) -> Result<Option<constraint::Version>> {
+    let output = match std::process::Command::new(engine.binary())
+        .args(["version", "-json"])
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("failed to run {}", engine.binary())),
+    };
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .with_context(|| format!("failed to parse `{} version -json`", engine.binary()))?;
+    match info.get("terraform_version").and_then(|v| v.as_str()) {
+        Some(v) => Ok(Some(constraint::Version::parse(v)?)),
+        None => Ok(None),
+    }
+}
+
 fn main() -> Result<()> {
     let args = Args::parse();
     let dir = args.dir.canonicalize().context("invalid directory")?;
-    let requested_version = args.version.or(args.version_flag);
+
+    if let Some(Command::Explain {
+        version,
+        constraint,
+    }) = &args.command
+    {
+        let constraint = match constraint {
+            Some(c) => VersionConstraint::parse(c)
+                .with_context(|| format!("invalid constraint: {}", c))?,
+            None => project_constraint(&dir, &args)?.0,
+        };
+        let satisfied = explain::run(
+            &dir,
+            &constraint,
+            args.engine(),
+            version.as_deref(),
+            &*args.nixpkgs_source()?,
+        )?;
+        if !satisfied {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let requested_version = args.version.clone().or(args.version_flag.clone());
     let verbose = args.verbose;
+    let engine = args.engine();
 
     if verbose {
-        if args.github_token.is_some() {
+        if let Some(path) = &args.nixpkgs_repo {
+            println!("Reading nixpkgs from {}", path.display());
+        } else if args.offline {
+            println!("Offline: answering from the nixpkgs cache only");
+        } else if args.github_token.is_some() {
             println!("Using GitHub token for API access");
         } else {
             println!("No GitHub token set (rate limit: 60 req/hr). Set GITHUB_TOKEN for 5,000 req/hr.");
         }
     }
 
-    // Step 1: Extract required_version from .tf files
-    let constraint_str = hcl::extract_required_version(&dir)?;
-    println!("Constraint: {}", constraint_str);
+    if args.per_root {
+        let strategy = resolve_strategy(
+            args.strategy.unwrap_or(StrategyArg::Highest),
+            engine,
+            verbose,
+        )?;
+        return generate_per_root(&dir, &args, &strategy, verbose);
+    }
 
-    let tf_constraint = constraint::VersionConstraint::parse(&constraint_str)?;
+    // Step 1: Extract the constraint from .tf files or version files
+    let (tf_constraint, implied_strategy) = project_constraint(&dir, &args)?;
+    let strategy = match args.strategy {
+        Some(arg) => resolve_strategy(arg, engine, verbose)?,
+        None => implied_strategy.unwrap_or(constraint::Strategy::Highest),
+    };
+    let constraint_str = tf_constraint.to_string();
+    println!("Constraint: {}", constraint_str);
+    if verbose {
+        let redundant = tf_constraint.redundant();
+        for req in tf_constraint.requirements() {
+            if redundant.iter().any(|r| std::ptr::eq(*r, req)) {
+                println!("  {} (implied by the others)", req);
+            } else {
+                println!("  {}", req);
+            }
+        }
+        println!("Allowed range: {}", tf_constraint.to_set());
+    }
 
     // Determine the effective constraint to search with
     let search_constraint = if let Some(ref ver_str) = requested_version {
@@ -66,66 +876,172 @@ fn main() -> Result<()> {
             );
         }
 
-        constraint::VersionConstraint::parse(&format!("= {}", requested))?
+        VersionConstraint::parse(&format!("= {}", requested))?
     } else {
         tf_constraint
     };
 
+    let source = args.nixpkgs_source()?;
+    let required = required_providers(&dir, &args)?;
+    let terragrunt = project_terragrunt(&dir, &args)?;
+    let packages = match terragrunt {
+        Some(_) => vec!["terragrunt".to_string()],
+        None => Vec::new(),
+    };
+    let terragrunt_constraint = terragrunt.and_then(|t| t.terragrunt);
+    if let Some(ref c) = terragrunt_constraint {
+        println!("Terragrunt constraint: {}", c);
+    }
+
     // Step 2: Check existing flake.nix
     let flake_path = dir.join("flake.nix");
     if flake_path.exists() {
-        match flake_check::check(&dir, &search_constraint, args.github_token.as_deref())? {
+        match flake_check::check(&dir, &search_constraint, engine, &*source)? {
             flake_check::CheckResult::Satisfied(version) => {
-                println!("Existing flake.nix already satisfies constraint (Terraform {})", version);
-                return Ok(());
+                println!(
+                    "Existing flake.nix already satisfies constraint ({} {})",
+                    engine, version
+                );
+                if complete_existing_flake(
+                    &dir,
+                    &args,
+                    &*source,
+                    &required,
+                    terragrunt_constraint.as_ref(),
+                    &packages,
+                )? {
+                    return Ok(());
+                }
             }
             flake_check::CheckResult::WrongVersion(version) => {
-                println!("Existing flake.nix has Terraform {} (not a match)", version);
+                println!("Existing flake.nix has {} {} (not a match)", engine, version);
             }
             flake_check::CheckResult::NotFound => {
-                println!("Existing flake.nix does not include Terraform");
+                println!("Existing flake.nix does not include {}", engine);
             }
             flake_check::CheckResult::Unknown => {
-                println!("Could not determine Terraform version in existing flake.nix");
+                println!("Could not determine {} version in existing flake.nix", engine);
             }
         }
     }
 
-    // Step 3: Find nixpkgs commit with matching Terraform version
+    // Step 3: Find nixpkgs commit with matching engine version
     if let Some(ref ver_str) = requested_version {
-        println!("Searching nixpkgs for Terraform {}...", ver_str);
+        println!("Searching nixpkgs for {} {}...", engine, ver_str);
     } else {
         println!(
-            "Searching nixpkgs for Terraform satisfying \"{}\"...",
-            constraint_str
+            "Searching nixpkgs for {} satisfying \"{}\"...",
+            engine, constraint_str
         );
     }
-    let (version, commit) =
-        nixpkgs::find_terraform_commit(&search_constraint, args.github_token.as_deref(), verbose)
-            .with_context(|| {
-                if let Some(ref ver_str) = requested_version {
-                    format!("Terraform {} not found in nixpkgs", ver_str)
-                } else {
-                    format!(
-                        "no Terraform version satisfying \"{}\" found in nixpkgs",
-                        constraint_str
-                    )
-                }
-            })?;
+    let requirements = nixpkgs::Requirements {
+        providers: if args.locked_providers { &required } else { &[] },
+        terragrunt: terragrunt_constraint.as_ref(),
+    };
+    let found = nixpkgs::find_engine_commit(
+        &search_constraint,
+        engine,
+        &requirements,
+        &strategy,
+        &args.search_options(),
+        &*source,
+        verbose,
+    )
+    .with_context(|| {
+        if let Some(ref ver_str) = requested_version {
+            format!("{} {} not found in nixpkgs", engine, ver_str)
+        } else {
+            format!(
+                "no {} version satisfying \"{}\" found in nixpkgs",
+                engine, constraint_str
+            )
+        }
+    })?;
+    let commit = found.commit;
     println!(
-        "Found Terraform {} at nixpkgs {}",
-        version,
-        &commit[..12]
+        "Found {} {} at nixpkgs {}",
+        engine,
+        found.version,
+        nixpkgs::short_sha(&commit)
     );
+    if let Some(ref v) = found.terragrunt {
+        println!("Found Terragrunt {}", v);
+    }
+
+    // With --locked-providers the search already resolved them
+    let resolution = if args.locked_providers || required.is_empty() {
+        found.providers
+    } else {
+        providers::resolve(&required, &packaged_providers(&commit, &*source)?)
+    };
+    let plugins = provider_plugins(resolution);
 
     // Step 4: Generate or update flake.nix
     if flake_path.exists() {
-        flake_update::update(&dir, &commit)?;
+        flake_update::update(&dir, engine, &commit, &plugins, &packages)?;
         println!("Updated flake.nix");
     } else {
-        flake_generate::generate(&dir, &commit)?;
+        flake_generate::generate(&dir, engine, &commit, &plugins, &packages)?;
         println!("Generated flake.nix");
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn test_shell_names() {
+        let names = shell_names(&[".", "stacks/app", "db"]).unwrap();
+        assert_eq!(names, ["default", "stacks-app", "db"]);
+        let clash = shell_names(&["a/b", "a-b"]).unwrap_err();
+        assert_eq!(
+            clash.to_string(),
+            "roots a/b and a-b would both get devShell \"a-b\"; leave one out with --exclude"
+        );
+        assert!(shell_names(&["default"]).is_err());
+    }
+
+    #[test]
+    fn test_input_names() {
+        let pin = |version: &str, commit: &str| {
+            (constraint::Version::parse(version).unwrap(), commit.to_string())
+        };
+        let pins = [
+            pin("1.5.7", "aaaaaaaaaa"),
+            pin("1.5.7", "bbbbbbbbbb"),
+            pin("1.6.0-beta1", "cccccccccc"),
+            pin("1.6.0-beta1", "dddddddddd"),
+            pin("1.7.0", "eeeeeeeeee"),
+        ];
+        assert_eq!(
+            input_names(&pins),
+            [
+                "nixpkgs",
+                "nixpkgs-1_5_7",
+                "nixpkgs-1_6_0-beta1-ccccccc",
+                "nixpkgs-1_6_0-beta1-ddddddd",
+                "nixpkgs-1_7_0",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unsupported_version_file_after_chosen_source() {
+        let dir = TestDir::new("sources");
+        std::fs::write(dir.join("main.tf"), "terraform {\n  required_version = \"~> 1.5.0\"\n}\n")
+            .unwrap();
+        std::fs::write(dir.join(".tool-versions"), "terraform system\n").unwrap();
+        let constraint = |sources: &str| {
+            let args = Args::parse_from(["tfg", "--constraint-sources", sources]);
+            project_constraint(&dir, &args).map(|(c, _)| c.to_string())
+        };
+
+        assert_eq!(constraint("required-version,tool-versions").unwrap(), "~> 1.5.0");
+        let err = constraint("tool-versions,required-version").unwrap_err();
+        assert!(format!("{:#}", err).contains("\"system\" in .tool-versions"), "{:#}", err);
+    }
+}
@@ -1,21 +1,52 @@
-use crate::constraint::{Version, VersionConstraint};
+use crate::cache::{self, Cache};
+use crate::constraint::{Strategy, Version, VersionConstraint};
+use crate::engine::Engine;
+use crate::git_repo::GitRepo;
+use crate::hcl::ProviderRequirement;
+use crate::parallel;
+use crate::providers::Resolution;
+use crate::version_set::VersionSet;
 use anyhow::{bail, Context, Result};
 use regex::Regex;
-use serde::Deserialize;
+use std::collections::hash_map::RandomState;
+use std::collections::HashSet;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 
-const TERRAFORM_PATHS: &[&str] = &[
-    "pkgs/by-name/te/terraform/package.nix",
-    "pkgs/applications/networking/cluster/terraform/default.nix",
+const TERRAGRUNT_PATHS: &[&str] = &[
+    "pkgs/by-name/te/terragrunt/package.nix",
+    "pkgs/applications/networking/cluster/terragrunt/default.nix",
 ];
 
+const PROVIDERS_PATH: &str = "pkgs/applications/networking/cluster/terraform-providers/providers.json";
+
 #[derive(Deserialize)]
 struct CommitInfo {
     sha: String,
 }
 
+#[derive(Deserialize)]
+struct GitCommit {
+    parents: Vec<CommitInfo>,
+}
+
 #[derive(Deserialize)]
 struct CommitListEntry {
     sha: String,
+    commit: CommitListDetails,
+}
+
+#[derive(Deserialize)]
+struct CommitListDetails {
+    committer: CommitListCommitter,
+}
+
+#[derive(Deserialize)]
+struct CommitListCommitter {
+    date: String,
 }
 
 #[derive(Deserialize)]
@@ -30,102 +61,715 @@ struct GitRefObject {
     sha: String,
 }
 
-fn make_client(token: Option<&str>) -> Result<reqwest::blocking::Client> {
-    let mut headers = reqwest::header::HeaderMap::new();
-    headers.insert(
-        reqwest::header::ACCEPT,
-        "application/vnd.github.v3+json".parse().unwrap(),
-    );
-    headers.insert(
-        reqwest::header::USER_AGENT,
-        "terraform-flake-generator".parse().unwrap(),
-    );
-    if let Some(token) = token {
+#[derive(Deserialize)]
+struct ProviderEntry {
+    #[serde(rename = "provider-source-address")]
+    source_address: Option<String>,
+    homepage: Option<String>,
+    version: String,
+}
+
+/// A Terraform provider packaged in nixpkgs' `terraform-providers` set.
+#[derive(Debug, Clone)]
+pub struct PackagedProvider {
+    /// Attribute name, as used in `terraform.withPlugins (p: [ p.<attr> ])`.
+    pub attr: String,
+    /// Registry address, e.g. `hashicorp/aws`.
+    pub source: String,
+    pub version: Version,
+}
+
+/// How many commits `NixpkgsSource::commits_touching` returns per page.
+const PER_PAGE: usize = 100;
+
+/// A commit in the history of a file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Commit {
+    pub sha: String,
+    /// Committer date, as `YYYY-MM-DDTHH:MM:SSZ`.
+    pub date: String,
+}
+
+/// A page of commits, and whether there are more after it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistoryPage {
+    pub commits: Vec<Commit>,
+    pub more: bool,
+}
+
+/// How far back tier 2 walks the package's history.
+#[derive(Debug, Clone, Default)]
+pub struct HistoryLimit {
+    /// At most this many commits.
+    pub depth: Option<usize>,
+    /// No commits from before this date, given as `YYYY-MM-DD`.
+    pub since: Option<String>,
+}
+
+/// How `find_engine_commit` searches.
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    pub history: HistoryLimit,
+    /// How many lookups run at once.
+    pub jobs: usize,
+}
+
+impl Default for SearchOptions {
+    fn default() -> SearchOptions {
+        SearchOptions {
+            history: HistoryLimit::default(),
+            jobs: 1,
+        }
+    }
+}
+
+/// Read access to a nixpkgs repository. `find_engine_commit` and the other
+/// lookups only go through these operations, so the search runs the same
+/// against GitHub, a local clone or a test fixture. Lookups may run on several
+/// threads at once.
+pub trait NixpkgsSource: Sync {
+    /// Branches whose name starts with `prefix`, as `(branch_name, sha)` pairs.
+    fn branches(&self, prefix: &str) -> Result<Vec<(String, String)>>;
+
+    /// The commit SHA a branch or other ref points to, or `None` if there is
+    /// no such ref.
+    fn resolve(&self, reference: &str) -> Result<Option<String>>;
+
+    /// The contents of `path` at a ref, or `None` if it doesn't exist there.
+    fn read_file(&self, reference: &str, path: &str) -> Result<Option<String>>;
+
+    /// Page `page` (counting from 1) of the commits reachable from `from`
+    /// that changed `path`, newest first.
+    fn commits_touching(&self, path: &str, from: &str, page: usize) -> Result<HistoryPage>;
+
+    /// The first parent of commit `sha`, or `None` for a root commit.
+    fn parent(&self, sha: &str) -> Result<Option<String>>;
+
+    /// The version of the package kept at `paths` (tried in order) at a ref.
+    fn package_version(&self, reference: &str, paths: &[&str]) -> Result<Option<String>> {
+        read_package_version(self, reference, paths)
+    }
+
+    /// The Terraform providers packaged at a ref, or `None` if it has no
+    /// `providers.json`.
+    fn packaged_providers(&self, reference: &str) -> Result<Option<Vec<PackagedProvider>>> {
+        read_packaged_providers(self, reference)
+    }
+}
+
+/// NixOS/nixpkgs on GitHub.
+///
+/// With a cache, package versions and packaged providers are read once per
+/// commit, and API responses are reused for `cache::REF_TTL`, then revalidated
+/// with their ETag. Offline, only the cache is asked, and anything it lacks is a
+/// `NotCached` error.
+///
+/// Server errors and dropped connections are retried with backoff. Hitting
+/// the rate limit is an error, unless `wait_for_rate_limit` is set, in which
+/// case requests wait for it to reset.
+pub struct GitHub {
+    client: reqwest::blocking::Client,
+    cache: Option<Cache>,
+    offline: bool,
+    wait_for_rate_limit: bool,
+    /// Whether the warning about running low on requests was shown.
+    warned: AtomicBool,
+}
+
+impl GitHub {
+    pub fn new(
+        token: Option<&str>,
+        cache: Option<Cache>,
+        offline: bool,
+        wait_for_rate_limit: bool,
+    ) -> Result<GitHub> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::ACCEPT,
+            "application/vnd.github.v3+json".parse().unwrap(),
+        );
         headers.insert(
-            reqwest::header::AUTHORIZATION,
-            format!("Bearer {}", token).parse().context("invalid token")?,
+            reqwest::header::USER_AGENT,
+            "terraform-flake-generator".parse().unwrap(),
         );
+        if let Some(token) = token {
+            headers.insert(
+                reqwest::header::AUTHORIZATION,
+                format!("Bearer {}", token).parse().context("invalid token")?,
+            );
+        }
+        let client = reqwest::blocking::Client::builder()
+            .default_headers(headers)
+            .build()
+            .context("failed to create HTTP client")?;
+        Ok(GitHub {
+            client,
+            cache,
+            offline,
+            wait_for_rate_limit,
+            warned: AtomicBool::new(false),
+        })
     }
-    reqwest::blocking::Client::builder()
-        .default_headers(headers)
-        .build()
-        .context("failed to create HTTP client")
-}
 
-/// Extract terraform version from a Nix expression source using regex.
-fn extract_version_from_nix(source: &str) -> Option<String> {
-    let re = Regex::new(r#"version\s*=\s*"(\d+\.\d+\.\d+)""#).unwrap();
-    re.captures(source).map(|c| c[1].to_string())
+    /// GET `url`, retrying server errors and failed connections, and stopping
+    /// at the rate limit rather than handing its 403 on as an answer.
+    fn send(&self, url: &str, etag: Option<&str>) -> Result<reqwest::blocking::Response> {
+        let mut attempt = 0;
+        loop {
+            let mut request = self.client.get(url);
+            if let Some(etag) = etag {
+                request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+            }
+            let now = cache::now();
+            let retry = match request.send() {
+                Ok(resp) => {
+                    if let Some(limited) = rate_limited(resp.status(), resp.headers(), now) {
+                        if !self.wait_for_rate_limit {
+                            return Err(limited.into());
+                        }
+                        eprintln!("GitHub rate limited until {}; waiting", clock(limited.until));
+                        let wait = limited.until.saturating_sub(now) + 1;
+                        std::thread::sleep(Duration::from_secs(wait));
+                        continue;
+                    }
+                    self.warn_if_running_low(resp.headers(), now);
+                    if !resp.status().is_server_error() || attempt == RETRIES {
+                        return Ok(resp);
+                    }
+                    format!("HTTP {}", resp.status())
+                }
+                Err(e) if (e.is_connect() || e.is_timeout()) && attempt < RETRIES => e.to_string(),
+                Err(e) => return Err(e).with_context(|| format!("GET {} failed", url)),
+            };
+            let delay = backoff(attempt);
+            eprintln!(
+                "Warning: GET {} failed ({}); retrying in {:.1}s",
+                url,
+                retry,
+                delay.as_secs_f64()
+            );
+            std::thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    fn warn_if_running_low(&self, headers: &reqwest::header::HeaderMap, now: u64) {
+        let remaining = header_number(headers, "x-ratelimit-remaining");
+        let reset = header_number(headers, "x-ratelimit-reset");
+        if let (Some(remaining), Some(reset)) = (remaining, reset) {
+            if remaining < LOW_RATE_LIMIT && !self.warned.swap(true, Ordering::Relaxed) {
+                eprintln!(
+                    "Warning: {} GitHub API requests left, resets at {}",
+                    remaining,
+                    clock(reset.max(now))
+                );
+            }
+        }
+    }
+
+    /// GET an API `url`, turning the response into a `T` with `parse`. Successful
+    /// answers are cached; a fresh one is used without asking GitHub, and a stale
+    /// one is revalidated with `If-None-Match`, which costs no rate limit.
+    fn api_get<T: Serialize + DeserializeOwned>(
+        &self,
+        url: &str,
+        parse: impl FnOnce(reqwest::blocking::Response) -> Result<T>,
+    ) -> Result<T> {
+        let cached = self.cache.as_ref().and_then(|c| c.response(url));
+        let now = cache::now();
+        if let Some(cached) = &cached {
+            if self.offline || cached.is_fresh(now) {
+                return serde_json::from_value(cached.value.clone())
+                    .context("failed to read cached response");
+            }
+        }
+        if self.offline {
+            return Err(NotCached(url.to_string()).into());
+        }
+
+        let resp = self.send(url, cached.as_ref().and_then(|c| c.etag.as_deref()))?;
+        if let (reqwest::StatusCode::NOT_MODIFIED, Some(cached), Some(cache)) =
+            (resp.status(), &cached, &self.cache)
+        {
+            cache.set_response(url, cache::Response { fetched: now, ..cached.clone() });
+            return serde_json::from_value(cached.value.clone())
+                .context("failed to read cached response");
+        }
+
+        let success = resp.status().is_success();
+        let etag = resp
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let value = parse(resp)?;
+        if let (true, Some(cache)) = (success, &self.cache) {
+            let response = cache::Response {
+                etag,
+                fetched: now,
+                value: serde_json::to_value(&value)?,
+            };
+            cache.set_response(url, response);
+        }
+        Ok(value)
+    }
 }
 
-/// Fetch the terraform Nix expression at a specific nixpkgs ref.
-/// Tries multiple known paths.
-fn fetch_terraform_nix(
-    client: &reqwest::blocking::Client,
-    nixpkgs_ref: &str,
-) -> Result<Option<String>> {
-    for path in TERRAFORM_PATHS {
+impl NixpkgsSource for GitHub {
+    fn branches(&self, prefix: &str) -> Result<Vec<(String, String)>> {
+        let url = format!(
+            "https://api.github.com/repos/NixOS/nixpkgs/git/matching-refs/heads/{}",
+            prefix
+        );
+        self.api_get(&url, |resp| {
+            if !resp.status().is_success() {
+                bail!("matching-refs API returned HTTP {}", resp.status());
+            }
+            let refs: Vec<GitRef> =
+                resp.json().context("failed to parse matching-refs response")?;
+            Ok(refs
+                .into_iter()
+                .filter_map(|r| {
+                    let name = r.ref_name.strip_prefix("refs/heads/")?.to_string();
+                    Some((name, r.object.sha))
+                })
+                .collect())
+        })
+    }
+
+    fn resolve(&self, reference: &str) -> Result<Option<String>> {
+        let url = format!(
+            "https://api.github.com/repos/NixOS/nixpkgs/commits/{}",
+            reference
+        );
+        self.api_get(&url, |resp| {
+            if matches!(
+                resp.status(),
+                reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::UNPROCESSABLE_ENTITY
+            ) {
+                return Ok(None);
+            }
+            if !resp.status().is_success() {
+                bail!("failed to resolve {}: HTTP {}", reference, resp.status());
+            }
+            let info: CommitInfo = resp.json().context("failed to parse commit info")?;
+            Ok(Some(info.sha))
+        })
+    }
+
+    fn read_file(&self, reference: &str, path: &str) -> Result<Option<String>> {
+        if self.offline {
+            return Err(NotCached(format!("{} at {}", path, reference)).into());
+        }
         let url = format!(
             "https://raw.githubusercontent.com/NixOS/nixpkgs/{}/{}",
-            nixpkgs_ref, path
+            reference, path
         );
-        let resp = client.get(&url).send().context("HTTP request failed")?;
-        if resp.status().is_success() {
-            let body = resp.text().context("failed to read response body")?;
-            return Ok(Some(body));
+        let resp = self.send(&url, None)?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !resp.status().is_success() {
+            bail!("failed to read {} at {}: HTTP {}", path, reference, resp.status());
         }
+        Ok(Some(resp.text().context("failed to read response body")?))
     }
-    Ok(None)
-}
 
-/// Resolve a branch name to its HEAD commit SHA.
-fn resolve_branch_sha(client: &reqwest::blocking::Client, branch: &str) -> Result<String> {
-    let url = format!(
-        "https://api.github.com/repos/NixOS/nixpkgs/commits/{}",
-        branch
-    );
-    let resp = client.get(&url).send().context("GitHub API request failed")?;
-    if !resp.status().is_success() {
-        bail!(
-            "failed to resolve branch {}: HTTP {}",
-            branch,
-            resp.status()
+    fn commits_touching(&self, path: &str, from: &str, page: usize) -> Result<HistoryPage> {
+        let url = format!(
+            "https://api.github.com/repos/NixOS/nixpkgs/commits?path={}&sha={}&per_page={}&page={}",
+            path, from, PER_PAGE, page
         );
+        self.api_get(&url, |resp| {
+            if !resp.status().is_success() {
+                bail!("failed to list commits touching {}: HTTP {}", path, resp.status());
+            }
+            let more = has_next_page(&resp);
+            let commits: Vec<CommitListEntry> =
+                resp.json().context("failed to parse commits list")?;
+            let commits = commits
+                .into_iter()
+                .map(|c| Commit {
+                    sha: c.sha,
+                    date: c.commit.committer.date,
+                })
+                .collect();
+            Ok(HistoryPage { commits, more })
+        })
+    }
+
+    fn parent(&self, sha: &str) -> Result<Option<String>> {
+        let url = format!(
+            "https://api.github.com/repos/NixOS/nixpkgs/git/commits/{}",
+            sha
+        );
+        self.api_get(&url, |resp| {
+            if !resp.status().is_success() {
+                bail!("failed to read commit {}: HTTP {}", sha, resp.status());
+            }
+            let commit: GitCommit = resp.json().context("failed to parse commit")?;
+            Ok(commit.parents.into_iter().next().map(|p| p.sha))
+        })
+    }
+
+    fn package_version(&self, reference: &str, paths: &[&str]) -> Result<Option<String>> {
+        // Only a commit SHA always refers to the same files
+        let cache = match &self.cache {
+            Some(cache) if is_commit_sha(reference) => cache,
+            _ => return read_package_version(self, reference, paths),
+        };
+        if let Some(version) = cache.version(reference, paths[0]) {
+            return Ok(version);
+        }
+        let version = read_package_version(self, reference, paths)?;
+        cache.set_version(reference, paths[0], version.clone());
+        Ok(version)
+    }
+
+    fn packaged_providers(&self, reference: &str) -> Result<Option<Vec<PackagedProvider>>> {
+        let cache = match &self.cache {
+            Some(cache) if is_commit_sha(reference) => cache,
+            _ => return read_packaged_providers(self, reference),
+        };
+        if let Some(providers) = cache.providers(reference) {
+            return Ok(providers.map(|providers| {
+                providers
+                    .into_iter()
+                    .filter_map(|p| {
+                        Some(PackagedProvider {
+                            version: Version::parse(&p.version).ok()?,
+                            attr: p.attr,
+                            source: p.source,
+                        })
+                    })
+                    .collect()
+            }));
+        }
+        let providers = read_packaged_providers(self, reference)?;
+        let cached = providers.as_ref().map(|providers| {
+            providers
+                .iter()
+                .map(|p| cache::Provider {
+                    attr: p.attr.clone(),
+                    source: p.source.clone(),
+                    version: p.version.to_string(),
+                })
+                .collect()
+        });
+        cache.set_providers(reference, cached);
+        Ok(providers)
     }
-    let info: CommitInfo = resp.json().context("failed to parse commit info")?;
-    Ok(info.sha)
 }
 
-/// Fetch recent nixpkgs branches dynamically from GitHub.
-/// Returns `(branch_name, sha)` pairs: `nixpkgs-unstable` followed by the 5 most
-/// recent `nixos-YY.MM` release branches.
-fn fetch_recent_branches(
-    client: &reqwest::blocking::Client,
-    verbose: bool,
-) -> Result<Vec<(String, String)>> {
-    let url = "https://api.github.com/repos/NixOS/nixpkgs/git/matching-refs/heads/nixos-";
-    let resp = client.get(url).send().context("GitHub matching-refs request failed")?;
-    if !resp.status().is_success() {
-        bail!("matching-refs API returned HTTP {}", resp.status());
+/// Whether GitHub links a next page of results.
+fn has_next_page(resp: &reqwest::blocking::Response) -> bool {
+    resp.headers()
+        .get(reqwest::header::LINK)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|link| link.contains("rel=\"next\""))
+}
+
+/// How often a request is retried after a server error or failed connection.
+const RETRIES: u32 = 4;
+
+/// Below this many requests left, a warning is shown.
+const LOW_RATE_LIMIT: u64 = 10;
+
+/// GitHub turning requests away until `until`, in seconds since the Unix epoch.
+#[derive(Debug, PartialEq)]
+struct RateLimited {
+    /// The requests allowed per hour, for the primary rate limit. Secondary
+    /// limits, on bursts of requests, come with `Retry-After` instead.
+    limit: Option<u64>,
+    until: u64,
+}
+
+impl std::fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.limit {
+            Some(limit) => write!(
+                f,
+                "GitHub rate limited, 0 of {} requests left, resets at {}; set GITHUB_TOKEN \
+                 for a higher limit, or pass --wait-for-rate-limit",
+                limit,
+                clock(self.until)
+            ),
+            None => write!(
+                f,
+                "GitHub rate limited, retry at {}; pass --wait-for-rate-limit to wait",
+                clock(self.until)
+            ),
+        }
     }
-    let refs: Vec<GitRef> = resp.json().context("failed to parse matching-refs response")?;
+}
 
-    let re = Regex::new(r"^refs/heads/(nixos-\d{2}\.\d{2})$").unwrap();
-    let mut release_branches: Vec<(String, String)> = refs
-        .into_iter()
-        .filter_map(|r| {
-            re.captures(&r.ref_name)
-                .map(|caps| (caps[1].to_string(), r.object.sha))
+impl std::error::Error for RateLimited {}
+
+/// Whether `e` is GitHub's rate limit, which must not pass for a missing answer.
+fn is_rate_limit(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<RateLimited>().is_some()
+}
+
+/// Something asked for offline that the cache doesn't have.
+#[derive(Debug)]
+struct NotCached(String);
+
+impl std::fmt::Display for NotCached {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} is not cached; run once without --offline", self.0)
+    }
+}
+
+impl std::error::Error for NotCached {}
+
+fn is_not_cached(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<NotCached>().is_some()
+}
+
+/// Whether a response with `status` and `headers`, received at `now`, means
+/// the rate limit was hit. GitHub answers that with 403 or 429.
+fn rate_limited(
+    status: reqwest::StatusCode,
+    headers: &reqwest::header::HeaderMap,
+    now: u64,
+) -> Option<RateLimited> {
+    if !matches!(
+        status,
+        reqwest::StatusCode::FORBIDDEN | reqwest::StatusCode::TOO_MANY_REQUESTS
+    ) {
+        return None;
+    }
+    if let Some(seconds) = header_number(headers, reqwest::header::RETRY_AFTER.as_str()) {
+        return Some(RateLimited {
+            limit: None,
+            until: now + seconds,
+        });
+    }
+    if header_number(headers, "x-ratelimit-remaining") != Some(0) {
+        return None;
+    }
+    Some(RateLimited {
+        limit: header_number(headers, "x-ratelimit-limit"),
+        until: header_number(headers, "x-ratelimit-reset").unwrap_or(now + 60),
+    })
+}
+
+fn header_number(headers: &reqwest::header::HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// How long to wait before retry `attempt`, counted from 0: doubling from a
+/// second up to 30, less a random part of up to half, so that runs failing
+/// together don't retry together.
+fn backoff(attempt: u32) -> Duration {
+    let delay = Duration::from_secs(1 << attempt.min(5)).min(Duration::from_secs(30));
+    let random = RandomState::new().build_hasher().finish();
+    let jitter = random % (delay.as_millis() as u64 / 2 + 1);
+    delay - Duration::from_millis(jitter)
+}
+
+/// `HH:MM:SS UTC` for a time in seconds since the Unix epoch.
+fn clock(time: u64) -> String {
+    let seconds = time % (24 * 60 * 60);
+    format!(
+        "{:02}:{:02}:{:02} UTC",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// A commit SHA cut down for messages. Shorter refs are shown whole.
+pub fn short_sha(sha: &str) -> &str {
+    sha.get(..12).unwrap_or(sha)
+}
+
+fn is_commit_sha(reference: &str) -> bool {
+    reference.len() == 40 && reference.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// A local nixpkgs clone, for searching offline and without rate limits.
+/// Remote-tracking branches count, and the full history is walked.
+impl NixpkgsSource for GitRepo {
+    fn branches(&self, prefix: &str) -> Result<Vec<(String, String)>> {
+        let mut branches = GitRepo::branches(self)?;
+        branches.retain(|(name, _)| name.starts_with(prefix));
+        Ok(branches)
+    }
+
+    fn resolve(&self, reference: &str) -> Result<Option<String>> {
+        let branches = GitRepo::branches(self)?;
+        Ok(branches.into_iter().find(|(name, _)| name == reference).map(|(_, sha)| sha))
+    }
+
+    fn read_file(&self, reference: &str, path: &str) -> Result<Option<String>> {
+        GitRepo::read_file(self, reference, path)
+    }
+
+    fn commits_touching(&self, path: &str, from: &str, page: usize) -> Result<HistoryPage> {
+        let from = self.resolve(from)?.unwrap_or_else(|| from.to_string());
+        let commits: Vec<Commit> = self
+            .history(&from, path, (page - 1) * PER_PAGE, PER_PAGE)?
+            .into_iter()
+            .map(|(sha, date)| Commit { sha, date })
+            .collect();
+        let more = commits.len() == PER_PAGE;
+        Ok(HistoryPage { commits, more })
+    }
+
+    fn parent(&self, sha: &str) -> Result<Option<String>> {
+        GitRepo::parent(self, sha)
+    }
+}
+
+/// An in-memory nixpkgs for tests: commits with their files and first parent,
+/// and branch heads. History is paged two commits at a time.
+#[cfg(test)]
+#[derive(Default)]
+pub struct Fixture {
+    /// `(branch_name, sha)`, including `master`.
+    branches: Vec<(String, String)>,
+    commits: Vec<FixtureCommit>,
+    /// The refs files were read at, in order.
+    reads: std::sync::Mutex<Vec<String>>,
+    /// How many package versions were looked up.
+    lookups: std::sync::atomic::AtomicUsize,
+}
+
+/// A commit, its first parent and its files.
+#[cfg(test)]
+type FixtureCommit = (Commit, Option<String>, Vec<(String, String)>);
+
+#[cfg(test)]
+impl Fixture {
+    /// Add a commit on top of master, with these files and no others.
+    pub fn commit(&mut self, sha: &str, date: &str, files: &[(&str, &str)]) {
+        self.commit_on("master", sha, date, files);
+    }
+
+    /// Add a commit on top of `branch`, moving it forward.
+    pub fn commit_on(&mut self, branch: &str, sha: &str, date: &str, files: &[(&str, &str)]) {
+        let parent = self.resolve(branch).unwrap();
+        let files = files.iter().map(|(p, c)| (p.to_string(), c.to_string())).collect();
+        let commit = Commit {
+            sha: sha.to_string(),
+            date: format!("{}T00:00:00Z", date),
+        };
+        self.commits.push((commit, parent, files));
+        self.branch(branch, sha);
+    }
+
+    /// Point `name` at `sha`.
+    pub fn branch(&mut self, name: &str, sha: &str) {
+        self.branches.retain(|(b, _)| b != name);
+        self.branches.push((name.to_string(), sha.to_string()));
+    }
+
+    /// The commits from `from` following first parents, newest first.
+    fn first_parents(&self, from: &str) -> Vec<&FixtureCommit> {
+        let mut chain = Vec::new();
+        let mut next = Some(self.resolve(from).unwrap().unwrap_or_else(|| from.to_string()));
+        while let Some(commit) = next.and_then(|sha| self.commits.iter().find(|c| c.0.sha == sha)) {
+            chain.push(commit);
+            next = commit.1.clone();
+        }
+        chain
+    }
+}
+
+#[cfg(test)]
+impl NixpkgsSource for Fixture {
+    fn branches(&self, prefix: &str) -> Result<Vec<(String, String)>> {
+        Ok(self.branches.iter().filter(|(b, _)| b.starts_with(prefix)).cloned().collect())
+    }
+
+    fn resolve(&self, reference: &str) -> Result<Option<String>> {
+        Ok(self.branches.iter().find(|(b, _)| b == reference).map(|(_, sha)| sha.clone()))
+    }
+
+    fn read_file(&self, reference: &str, path: &str) -> Result<Option<String>> {
+        self.reads.lock().unwrap().push(reference.to_string());
+        Ok(self
+            .first_parents(reference)
+            .first()
+            .and_then(|(_, _, files)| files.iter().find(|(p, _)| p == path))
+            .map(|(_, content)| content.clone()))
+    }
+
+    fn commits_touching(&self, path: &str, from: &str, page: usize) -> Result<HistoryPage> {
+        // A commit touches a path if the file differs from the commit before it
+        let content = |files: &[(String, String)]| {
+            files.iter().find(|(p, _)| p == path).map(|(_, c)| c.clone())
+        };
+        let chain = self.first_parents(from);
+        let mut touching = Vec::new();
+        for (i, (commit, _, files)) in chain.iter().enumerate() {
+            let before = chain.get(i + 1).and_then(|(_, _, f)| content(f));
+            if content(files) != before {
+                touching.push(commit.clone());
+            }
+        }
+
+        let commits: Vec<Commit> = touching.into_iter().skip((page - 1) * 2).collect();
+        let more = commits.len() > 2;
+        Ok(HistoryPage {
+            commits: commits.into_iter().take(2).collect(),
+            more,
         })
+    }
+
+    fn parent(&self, sha: &str) -> Result<Option<String>> {
+        Ok(self.commits.iter().find(|c| c.0.sha == sha).and_then(|c| c.1.clone()))
+    }
+
+    fn package_version(&self, reference: &str, paths: &[&str]) -> Result<Option<String>> {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        read_package_version(self, reference, paths)
+    }
+}
+
+/// Extract terraform version from a Nix expression source using regex.
+fn extract_version_from_nix(source: &str) -> Option<String> {
+    let re = Regex::new(r#"version\s*=\s*"(\d+\.\d+\.\d+(?:-[0-9A-Za-z.-]+)?(?:\+[0-9A-Za-z.-]+)?)""#)
+        .unwrap();
+    re.captures(source).map(|c| c[1].to_string())
+}
+
+/// Read a package's version from its Nix expression at a specific nixpkgs ref.
+/// Tries each of the package's known paths.
+fn read_package_version<S: NixpkgsSource + ?Sized>(
+    source: &S,
+    nixpkgs_ref: &str,
+    paths: &[&str],
+) -> Result<Option<String>> {
+    for path in paths {
+        if let Some(body) = source.read_file(nixpkgs_ref, path)? {
+            return Ok(extract_version_from_nix(&body));
+        }
+    }
+    Ok(None)
+}
+
+/// The recent nixpkgs branches to check first, as `(branch_name, sha)` pairs:
+/// `nixpkgs-unstable` followed by the 5 most recent `nixos-YY.MM` release branches.
+fn recent_branches(source: &dyn NixpkgsSource, verbose: bool) -> Result<Vec<(String, String)>> {
+    let re = Regex::new(r"^nixos-\d{2}\.\d{2}$").unwrap();
+    let mut release_branches: Vec<(String, String)> = source
+        .branches("nixos-")?
+        .into_iter()
+        .filter(|(b, _)| re.is_match(b))
         .collect();
 
     // Descending sort by name — fixed-width YY.MM format sorts correctly
     release_branches.sort_by(|a, b| b.0.cmp(&a.0));
     release_branches.truncate(5);
 
-    // Prepend nixpkgs-unstable (need to resolve its SHA separately)
-    let unstable_sha = resolve_branch_sha(client, "nixpkgs-unstable")?;
-    let mut branches = vec![("nixpkgs-unstable".to_string(), unstable_sha)];
+    // A local clone may not have fetched nixpkgs-unstable
+    let mut branches: Vec<(String, String)> = source
+        .resolve("nixpkgs-unstable")?
+        .map(|sha| ("nixpkgs-unstable".to_string(), sha))
+        .into_iter()
+        .collect();
     branches.extend(release_branches);
 
     if verbose {
@@ -139,53 +783,153 @@ fn fetch_recent_branches(
     Ok(branches)
 }
 
-/// Fetch the terraform version at a specific nixpkgs commit.
-pub fn terraform_version_at_commit(
+/// List the Terraform providers packaged at a nixpkgs commit.
+/// Entries whose address or version can't be determined are skipped.
+pub fn providers_at_commit(
     commit: &str,
-    token: Option<&str>,
+    source: &dyn NixpkgsSource,
+) -> Result<Vec<PackagedProvider>> {
+    match source.packaged_providers(commit)? {
+        Some(providers) => Ok(providers),
+        None => bail!("no terraform providers.json at {}", commit),
+    }
+}
+
+/// Read and parse `providers.json` at a ref, if there is one.
+fn read_packaged_providers<S: NixpkgsSource + ?Sized>(
+    source: &S,
+    reference: &str,
+) -> Result<Option<Vec<PackagedProvider>>> {
+    match source.read_file(reference, PROVIDERS_PATH)? {
+        Some(body) => parse_providers_json(&body).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn parse_providers_json(body: &str) -> Result<Vec<PackagedProvider>> {
+    let entries: std::collections::BTreeMap<String, ProviderEntry> =
+        serde_json::from_str(body).context("failed to parse providers.json")?;
+
+    let homepage_re = Regex::new(r"/providers/([^/]+)/([^/]+)/?$").unwrap();
+    let mut providers = Vec::new();
+    for (attr, entry) in entries {
+        // Older snapshots only have the registry homepage to go by
+        let source = match (entry.source_address, entry.homepage) {
+            (Some(address), _) => address
+                .to_lowercase()
+                .trim_start_matches("registry.terraform.io/")
+                .trim_start_matches("registry.opentofu.org/")
+                .to_string(),
+            (None, Some(homepage)) => match homepage_re.captures(&homepage) {
+                Some(c) => format!("{}/{}", &c[1], &c[2]).to_lowercase(),
+                None => continue,
+            },
+            (None, None) => continue,
+        };
+        let version = match Version::parse(&entry.version) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        providers.push(PackagedProvider {
+            attr,
+            source,
+            version,
+        });
+    }
+    Ok(providers)
+}
+
+/// Fetch the version of `engine` at a specific nixpkgs commit.
+pub fn engine_version_at_commit(
+    engine: Engine,
+    commit: &str,
+    source: &dyn NixpkgsSource,
 ) -> Result<Option<String>> {
-    let client = make_client(token)?;
-    let nix_source = fetch_terraform_nix(&client, commit)?;
-    Ok(nix_source.and_then(|s| extract_version_from_nix(&s)))
+    source.package_version(commit, engine.nix_paths())
+}
+
+/// Fetch the terragrunt version at a specific nixpkgs commit.
+pub fn terragrunt_version_at_commit(
+    commit: &str,
+    source: &dyn NixpkgsSource,
+) -> Result<Option<String>> {
+    source.package_version(commit, TERRAGRUNT_PATHS)
+}
+
+/// What the chosen commit must provide besides a matching terraform.
+#[derive(Debug, Default)]
+pub struct Requirements<'a> {
+    /// Providers the packaged `terraform-providers` should match.
+    pub providers: &'a [ProviderRequirement],
+    /// A constraint the terragrunt package must satisfy.
+    pub terragrunt: Option<&'a VersionConstraint>,
+}
+
+/// A nixpkgs commit chosen by `find_engine_commit`.
+#[derive(Debug)]
+pub struct Found {
+    pub version: Version,
+    pub commit: String,
+    /// How the requested providers matched the packages at `commit`.
+    pub providers: Resolution,
+    /// The terragrunt version at `commit`, if terragrunt was constrained.
+    pub terragrunt: Option<Version>,
 }
 
-/// Find a nixpkgs commit that provides a terraform version satisfying the constraint.
-/// Returns (version, commit_sha).
-pub fn find_terraform_commit(
+/// A commit whose terraform satisfies the constraint.
+struct Candidate {
+    version: Version,
+    sha: String,
+    stable: bool,
+    providers: Resolution,
+    terragrunt: Option<Version>,
+}
+
+/// Find a nixpkgs commit that provides a version of `engine` satisfying the constraint,
+/// choosing among matches according to `strategy`.
+///
+/// A terragrunt constraint in `requirements` rules out commits whose terragrunt
+/// doesn't satisfy it. If providers are given, the packaged providers must
+/// match them too: commits missing the fewest providers win, and if none has
+/// all of them, the best one is still returned and `Found::providers` reports
+/// what is missing.
+///
+/// Branch heads, release branches and history are each looked up on
+/// `options.jobs` threads, but considered in order, so the result doesn't
+/// depend on which lookup finishes first.
+pub fn find_engine_commit(
     constraint: &VersionConstraint,
-    token: Option<&str>,
+    engine: Engine,
+    requirements: &Requirements,
+    strategy: &Strategy,
+    options: &SearchOptions,
+    source: &dyn NixpkgsSource,
     verbose: bool,
-) -> Result<(Version, String)> {
-    let client = make_client(token)?;
-    let mut candidates: Vec<(Version, String)> = Vec::new();
+) -> Result<Found> {
+    let mut candidates: Vec<Candidate> = Vec::new();
+    let allowed = constraint.to_set();
+    let mut moved_past = Vec::new();
+    // Master's history holds nothing newer than the newest branch head
+    let mut newest: Option<Version> = None;
 
     // Tier 1: Check branch HEADs
-    let branches = fetch_recent_branches(&client, verbose)?;
+    let branches = recent_branches(source, verbose)?;
     if verbose {
         eprintln!("Checking nixpkgs branch HEADs...");
     }
-    for (branch, sha) in &branches {
+    let lookup = |(_, sha): &(String, String)| source.package_version(sha, engine.nix_paths());
+    parallel::for_each_ordered(&branches, options.jobs, lookup, |(branch, sha), version| {
         if verbose {
             eprint!("  {}... ", branch);
         }
 
-        let nix_source = match fetch_terraform_nix(&client, sha)? {
-            Some(s) => s,
-            None => {
-                if verbose {
-                    eprintln!("terraform package not found");
-                }
-                continue;
-            }
-        };
-
-        let version_str = match extract_version_from_nix(&nix_source) {
+        let version_str = match version? {
             Some(v) => v,
             None => {
                 if verbose {
-                    eprintln!("could not extract version");
+                    eprintln!("{} package not found", engine.package());
                 }
-                continue;
+                return Ok(true);
             }
         };
 
@@ -195,71 +939,909 @@ pub fn find_terraform_commit(
                 if verbose {
                     eprintln!("invalid version: {}", version_str);
                 }
-                continue;
+                return Ok(true);
             }
         };
 
         if verbose {
-            eprintln!("terraform {} ({})", version, &sha[..12]);
+            eprintln!("{} {} ({})", engine.package(), version, short_sha(sha));
         }
 
+        if newest.as_ref().is_none_or(|newest| version > *newest) {
+            newest = Some(version.clone());
+        }
+        let stable = branch.starts_with("nixos-");
         if constraint.matches(&version) {
-            candidates.push((version, sha.clone()));
+            candidates.extend(candidate(source, sha, version, stable, requirements, verbose)?);
+        } else if stable && allowed.is_below(&version) {
+            moved_past.push((branch.clone(), sha.clone()));
         }
+        Ok(true)
+    })?;
+
+    // A complete match in tier 1 only ends the search if nothing from the
+    // release branches or history could beat it
+    let below = newest.as_ref();
+    if let Some(best) = below.and_then(|v| settled(constraint, &candidates, strategy, v)) {
+        return Ok(found(best));
     }
 
-    // If we found matches in tier 1, pick the best
-    if let Some((version, sha)) = constraint.best_match(&candidates) {
-        return Ok((version.clone(), sha.clone()));
+    // Tier 1b: Release branches whose head has moved past the constraint may
+    // still have carried a matching version earlier on
+    if !moved_past.is_empty() && verbose {
+        eprintln!("Bisecting release branches...");
+    }
+    let bisect = |(_, head): &(String, String)| bisect_branch(source, engine, &allowed, head);
+    parallel::for_each_ordered(&moved_past, options.jobs, bisect, |(branch, _), last| {
+        let last = match last {
+            Ok(last) => last,
+            Err(e) if is_rate_limit(&e) => return Err(e),
+            Err(e) => {
+                if verbose {
+                    eprintln!("  {}: {:#}", branch, e);
+                }
+                return Ok(true);
+            }
+        };
+        match last {
+            Some((sha, version)) if constraint.matches(&version) => {
+                if verbose {
+                    let package = engine.package();
+                    eprintln!("  {}: {} {} ({})", branch, package, version, short_sha(&sha));
+                }
+                candidates.extend(candidate(source, &sha, version, true, requirements, verbose)?);
+            }
+            _ => {
+                if verbose {
+                    eprintln!("  {}: no match", branch);
+                }
+            }
+        }
+        Ok(true)
+    })?;
+    if let Some(best) = below.and_then(|v| settled(constraint, &candidates, strategy, v)) {
+        return Ok(found(best));
     }
 
-    // Tier 2: Walk commit history
+    // Tier 2: Walk commit history, until it gets older than anything the
+    // constraint allows, or than anything that could beat the best match
     if verbose {
-        eprintln!("No match in branch HEADs, walking commit history...");
+        eprintln!("Walking commit history...");
     }
-    for path in TERRAFORM_PATHS {
-        let url = format!(
-            "https://api.github.com/repos/NixOS/nixpkgs/commits?path={}&per_page=100",
-            path
-        );
-        let resp = client.get(&url).send().context("GitHub API request failed")?;
-        if !resp.status().is_success() {
-            continue;
+    let lookup = |commit: &Commit| source.package_version(&commit.sha, engine.nix_paths());
+    walk_history(source, engine.nix_paths(), options, verbose, lookup, |commit, version| {
+        let sha = &commit.sha;
+        let version_str = match version {
+            Ok(Some(v)) => v,
+            Ok(None) => return Ok(true),
+            // Offline, the history goes as far as earlier runs read it
+            Err(e) if is_not_cached(&e) => {
+                if verbose {
+                    eprintln!("  {}: {:#}", short_sha(sha), e);
+                }
+                return Ok(true);
+            }
+            Err(e) => return Err(e),
+        };
+
+        let version = match Version::parse(&version_str) {
+            Ok(v) => v,
+            Err(_) => return Ok(true),
+        };
+
+        if verbose {
+            eprint!("  {} {} {}... ", short_sha(sha), engine.package(), version);
         }
 
-        let commits: Vec<CommitListEntry> =
-            resp.json().context("failed to parse commits list")?;
+        if constraint.matches(&version) {
+            if verbose {
+                eprintln!("match!");
+            }
+            // History commits all come from master, so there is no stable preference here
+            let found = candidate(source, sha, version.clone(), false, requirements, verbose)?;
+            candidates.extend(found);
+        } else if allowed.is_above(&version) {
+            if verbose {
+                eprintln!("older than the constraint allows, stopping");
+            }
+            return Ok(false);
+        } else if verbose {
+            eprintln!("no match");
+        }
 
-        for commit in &commits {
-            let nix_source = match fetch_terraform_nix(&client, &commit.sha)? {
-                Some(s) => s,
-                None => continue,
-            };
+        // Older commits have older versions, which can't beat a complete match
+        // the strategy prefers to all of them
+        if settled(constraint, &candidates, strategy, &version).is_some() {
+            if verbose {
+                eprintln!("  no older version can do better, stopping");
+            }
+            return Ok(false);
+        }
+        Ok(true)
+    })?;
 
-            let version_str = match extract_version_from_nix(&nix_source) {
-                Some(v) => v,
-                None => continue,
+    if let Some(best) = pick(constraint, &candidates, strategy) {
+        return Ok(found(best));
+    }
+
+    bail!(
+        "could not find a nixpkgs commit with a {} version satisfying the constraint",
+        engine.package()
+    )
+}
+
+/// Find the newest commit on a release branch, from its `head`, whose
+/// `engine` isn't newer than `allowed`, given that the head's is. The commits
+/// that changed the package are listed newest first, the branch's own version
+/// bumps before master's from before the fork, and bisected for the oldest
+/// bump past `allowed`. Its first parent is the last commit before that bump,
+/// so it still carries the version the bump replaced. Usually that is one page
+/// of the listing, a handful of lookups and the parent.
+///
+/// `engine`'s paths are followed as in `walk_history`. A listed commit without
+/// the package at that path, such as the one that moved it away, counts as too
+/// new. Returns the commit and its version, if there is one.
+fn bisect_branch(
+    source: &dyn NixpkgsSource,
+    engine: Engine,
+    allowed: &VersionSet,
+    head: &str,
+) -> Result<Option<(String, Version)>> {
+    let version_at = |path: &str, commit: &Commit| -> Result<Option<Version>> {
+        Ok(source
+            .package_version(&commit.sha, &[path])?
+            .and_then(|v| Version::parse(&v).ok()))
+    };
+    let too_new = |version: &Option<Version>| version.as_ref().is_none_or(|v| allowed.is_below(v));
+
+    // The oldest commit seen so far that is too new
+    let mut bump: Option<String> = None;
+    let mut from = head.to_string();
+    for path in engine.nix_paths() {
+        let mut oldest = None;
+        for page in 1.. {
+            let history = source.commits_touching(path, &from, page)?;
+            let commits = history.commits;
+            let last = match commits.last() {
+                Some(commit) => commit,
+                None => break,
             };
+            oldest = Some(last.sha.clone());
+            let mut kept = version_at(path, last)?;
+            // The whole page is too new; move on to older commits
+            if too_new(&kept) {
+                bump = Some(last.sha.clone());
+                if !history.more {
+                    break;
+                }
+                continue;
+            }
 
-            let version = match Version::parse(&version_str) {
-                Ok(v) => v,
-                Err(_) => continue,
+            // commits[..lo] are too new and commits[hi] isn't
+            let (mut lo, mut hi) = (0, commits.len() - 1);
+            while lo < hi {
+                let mid = (lo + hi) / 2;
+                let version = version_at(path, &commits[mid])?;
+                if too_new(&version) {
+                    lo = mid + 1;
+                } else {
+                    hi = mid;
+                    kept = version;
+                }
+            }
+            if hi > 0 {
+                bump = Some(commits[hi - 1].sha.clone());
+            }
+            let sha = match bump {
+                Some(bump) => source.parent(&bump)?,
+                None => None,
             };
+            let sha = sha.unwrap_or_else(|| commits[hi].sha.clone());
+            return Ok(kept.map(|version| (sha, version)));
+        }
+        if let Some(sha) = oldest {
+            from = sha;
+        }
+    }
+    Ok(None)
+}
 
-            if verbose {
-                eprint!("  {} terraform {}... ", &commit.sha[..12], version);
+/// Walk the history of a package on master, newest first, handing each commit
+/// and what `lookup` found there to `visit` until it returns `false` or the
+/// history limit in `options` is reached. Lookups run ahead on `options.jobs`
+/// threads; once `visit` stops, no more are started. `paths` are where the
+/// package has lived, newest first: once the history at one path runs out at
+/// the commit that moved the package there, as in the migration to
+/// `pkgs/by-name`, the walk carries on with the previous path from that commit.
+fn walk_history<R: Send>(
+    source: &dyn NixpkgsSource,
+    paths: &[&str],
+    options: &SearchOptions,
+    verbose: bool,
+    lookup: impl Fn(&Commit) -> R + Sync,
+    mut visit: impl FnMut(&Commit, R) -> Result<bool>,
+) -> Result<()> {
+    let limit = &options.history;
+    let mut seen = HashSet::new();
+    let mut from = "master".to_string();
+    for path in paths {
+        let mut oldest = None;
+        for page in 1.. {
+            let history = match source.commits_touching(path, &from, page) {
+                Ok(history) => history,
+                // Offline, the history goes as far as earlier runs listed it
+                Err(e) if is_not_cached(&e) => {
+                    if verbose {
+                        eprintln!("Stopping: {:#}", e);
+                    }
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            };
+            // The page's new commits, up to the first one past the limit
+            let mut commits = Vec::new();
+            let mut stop = None;
+            for commit in history.commits {
+                oldest = Some(commit.sha.clone());
+                if !seen.insert(commit.sha.clone()) {
+                    continue;
+                }
+                if let Some(depth) = limit.depth.filter(|&d| seen.len() > d) {
+                    stop = Some(format!("Stopping after {} commits (--history-depth)", depth));
+                    break;
+                }
+                if let Some(since) = limit.since.as_deref().filter(|s| commit.date.as_str() < *s) {
+                    stop = Some(format!(
+                        "Stopping at commits from before {} (--history-since)",
+                        since
+                    ));
+                    break;
+                }
+                commits.push(commit);
+            }
+            if !parallel::for_each_ordered(&commits, options.jobs, &lookup, &mut visit)? {
+                return Ok(());
+            }
+            if let Some(stop) = stop {
+                if verbose {
+                    eprintln!("{}", stop);
+                }
+                return Ok(());
             }
+            if !history.more {
+                break;
+            }
+        }
+        if let Some(sha) = oldest {
+            from = sha;
+        }
+    }
+    Ok(())
+}
 
-            if constraint.matches(&version) {
+/// Check the rest of `requirements` at a commit whose terraform matches.
+/// `None` if its terragrunt rules it out.
+fn candidate(
+    source: &dyn NixpkgsSource,
+    sha: &str,
+    version: Version,
+    stable: bool,
+    requirements: &Requirements,
+    verbose: bool,
+) -> Result<Option<Candidate>> {
+    let mut terragrunt = None;
+    if let Some(wanted) = requirements.terragrunt {
+        let found = source
+            .package_version(sha, TERRAGRUNT_PATHS)?
+            .and_then(|v| Version::parse(&v).ok());
+        match found {
+            Some(v) if wanted.matches(&v) => terragrunt = Some(v),
+            Some(v) => {
                 if verbose {
-                    eprintln!("match!");
+                    eprintln!("    terragrunt {} does not satisfy \"{}\"", v, wanted);
                 }
-                return Ok((version, commit.sha.clone()));
-            } else if verbose {
-                eprintln!("no match");
+                return Ok(None);
+            }
+            None => {
+                if verbose {
+                    eprintln!("    terragrunt package not found");
+                }
+                return Ok(None);
             }
         }
     }
 
-    bail!("could not find a nixpkgs commit with a terraform version satisfying the constraint")
+    Ok(Some(Candidate {
+        providers: match_providers(source, sha, requirements.providers, verbose)?,
+        version,
+        sha: sha.to_string(),
+        stable,
+        terragrunt,
+    }))
+}
+
+/// Resolve `providers` against the packages at `sha`, skipping the download
+/// when there is nothing to resolve.
+fn match_providers(
+    source: &dyn NixpkgsSource,
+    sha: &str,
+    providers: &[ProviderRequirement],
+    verbose: bool,
+) -> Result<Resolution> {
+    if providers.is_empty() {
+        return Ok(Resolution::default());
+    }
+    // Older snapshots may predate providers.json; then nothing matches
+    let packaged = match providers_at_commit(sha, source) {
+        Ok(p) => p,
+        Err(e) if is_rate_limit(&e) || is_not_cached(&e) => return Err(e),
+        Err(e) => {
+            if verbose {
+                eprintln!("    {:#}", e);
+            }
+            Vec::new()
+        }
+    };
+    let resolution = crate::providers::resolve(providers, &packaged);
+    if verbose {
+        eprintln!(
+            "    {} of {} providers match at {}",
+            providers.len() - resolution.unmatched.len(),
+            providers.len(),
+            short_sha(sha)
+        );
+    }
+    Ok(resolution)
+}
+
+/// The best candidate: among those missing the fewest providers, the one
+/// `strategy` prefers, looking at stable branches first for `PreferStable`.
+fn pick<'a>(
+    constraint: &VersionConstraint,
+    candidates: &'a [Candidate],
+    strategy: &Strategy,
+) -> Option<&'a Candidate> {
+    let fewest = candidates.iter().map(|c| c.providers.unmatched.len()).min()?;
+    let pool = |stable_only: bool| -> Vec<(Version, String)> {
+        candidates
+            .iter()
+            .filter(|c| c.providers.unmatched.len() == fewest && (c.stable || !stable_only))
+            .map(|c| (c.version.clone(), c.sha.clone()))
+            .collect()
+    };
+
+    let best = |pool: Vec<(Version, String)>| {
+        let sha = constraint.best_match(&pool, strategy)?.1.clone();
+        candidates.iter().find(|c| c.sha == sha)
+    };
+    match strategy {
+        Strategy::PreferStable => best(pool(true)).or_else(|| best(pool(false))),
+        _ => best(pool(false)),
+    }
+}
+
+/// The best candidate, if it is a complete match that no version up to
+/// `below` could beat: `strategy` prefers it to all of them, or it is on a
+/// stable branch for `PreferStable`, which the history's commits aren't.
+fn settled<'a>(
+    constraint: &VersionConstraint,
+    candidates: &'a [Candidate],
+    strategy: &Strategy,
+    below: &Version,
+) -> Option<&'a Candidate> {
+    pick(constraint, candidates, strategy).filter(|best| {
+        best.providers.unmatched.is_empty()
+            && (best.stable && *strategy == Strategy::PreferStable
+                || !strategy.could_improve_below(&best.version, below))
+    })
+}
+
+fn found(candidate: &Candidate) -> Found {
+    Found {
+        version: candidate.version.clone(),
+        commit: candidate.sha.clone(),
+        providers: candidate.providers.clone(),
+        terragrunt: candidate.terragrunt.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn candidate(version: &str, sha: &str, stable: bool, unmatched: usize) -> Candidate {
+        Candidate {
+            version: Version::parse(version).unwrap(),
+            sha: sha.to_string(),
+            stable,
+            providers: Resolution {
+                plugins: Vec::new(),
+                unmatched: vec!["example/missing".to_string(); unmatched],
+            },
+            terragrunt: None,
+        }
+    }
+
+    #[test]
+    fn test_pick_prefers_complete_provider_matches() {
+        let constraint = VersionConstraint::parse(">= 1.5").unwrap();
+        let candidates = [
+            candidate("1.9.0", "unstable", false, 1),
+            candidate("1.8.0", "nixos-24.11", true, 0),
+            candidate("1.7.0", "nixos-24.05", true, 0),
+            candidate("1.6.0", "history", false, 0),
+        ];
+
+        let best = pick(&constraint, &candidates, &Strategy::Highest).unwrap();
+        assert_eq!(best.sha, "nixos-24.11");
+        let best = pick(&constraint, &candidates, &Strategy::Lowest).unwrap();
+        assert_eq!(best.sha, "history");
+        let best = pick(&constraint, &candidates, &Strategy::PreferStable).unwrap();
+        assert_eq!(best.sha, "nixos-24.11");
+
+        // Without a complete match, the closest one still wins
+        let partial = [
+            candidate("1.9.0", "unstable", false, 2),
+            candidate("1.8.0", "nixos-24.11", true, 1),
+        ];
+        let best = pick(&constraint, &partial, &Strategy::Highest).unwrap();
+        assert_eq!(best.sha, "nixos-24.11");
+        assert!(pick(&constraint, &[], &Strategy::Highest).is_none());
+    }
+
+    fn package(name: &str, version: &str) -> String {
+        format!("{{\n  pname = \"{}\";\n  version = \"{}\";\n}}\n", name, version)
+    }
+
+    fn find(
+        source: &dyn NixpkgsSource,
+        constraint: &str,
+        requirements: &Requirements,
+        strategy: Strategy,
+    ) -> Result<(String, String)> {
+        let constraint = VersionConstraint::parse(constraint).unwrap();
+        let found = find_engine_commit(
+            &constraint,
+            Engine::Terraform,
+            requirements,
+            &strategy,
+            &SearchOptions::default(),
+            source,
+            false,
+        )?;
+        Ok((found.version.to_string(), found.commit))
+    }
+
+    #[test]
+    fn test_tier1_branch_heads() {
+        let path = Engine::Terraform.nix_paths()[0];
+        let mut nixpkgs = Fixture::default();
+        for (sha, version) in [("a", "1.6.0"), ("b", "1.7.0"), ("c", "1.8.0"), ("d", "1.9.0")] {
+            nixpkgs.commit(sha, "2024-01-01", &[(path, &package("terraform", version))]);
+        }
+        nixpkgs.branch("nixos-23.11", "a");
+        nixpkgs.branch("nixos-24.05", "b");
+        nixpkgs.branch("nixos-24.05-small", "c");
+        nixpkgs.branch("nixpkgs-unstable", "d");
+        let none = Requirements::default();
+
+        let highest = find(&nixpkgs, ">= 1.6", &none, Strategy::Highest).unwrap();
+        assert_eq!(highest, ("1.9.0".to_string(), "d".to_string()));
+        let stable = find(&nixpkgs, ">= 1.6", &none, Strategy::PreferStable).unwrap();
+        assert_eq!(stable, ("1.7.0".to_string(), "b".to_string()));
+        let lowest = find(&nixpkgs, ">= 1.6", &none, Strategy::Lowest).unwrap();
+        assert_eq!(lowest, ("1.6.0".to_string(), "a".to_string()));
+
+        // -small channels aren't among the branches checked, but master's history is
+        let small = find(&nixpkgs, "~> 1.8.0", &none, Strategy::PreferStable).unwrap();
+        assert_eq!(small, ("1.8.0".to_string(), "c".to_string()));
+    }
+
+    #[test]
+    fn test_tier1_match_beaten_by_history() {
+        let path = Engine::Terraform.nix_paths()[0];
+        let mut nixpkgs = Fixture::default();
+        let history = [("a", "1.5.0"), ("b", "1.6.0"), ("c", "1.7.0"), ("d", "1.8.0")];
+        for (sha, version) in history {
+            nixpkgs.commit(sha, "2024-01-01", &[(path, &package("terraform", version))]);
+        }
+        nixpkgs.branch("nixos-24.05", "c");
+        nixpkgs.branch("nixpkgs-unstable", "d");
+        let none = Requirements::default();
+        let find = |strategy| {
+            let found = find(&nixpkgs, ">= 1.6", &none, strategy).unwrap();
+            (found.1, nixpkgs.lookups.swap(0, Ordering::Relaxed))
+        };
+
+        // Nothing in history is newer than the unstable head
+        assert_eq!(find(Strategy::Highest), ("d".to_string(), 2));
+        // Nor can history beat a stable head for PreferStable
+        assert_eq!(find(Strategy::PreferStable), ("c".to_string(), 2));
+        // Older versions than either head are only in history
+        assert_eq!(find(Strategy::Lowest), ("b".to_string(), 6));
+        let closest = Strategy::Closest(Version::parse("1.6.2").unwrap());
+        assert_eq!(find(closest), ("b".to_string(), 5));
+    }
+
+    #[test]
+    fn test_tier2_history() {
+        let by_name = Engine::Terraform.nix_paths()[0];
+        let legacy = Engine::Terraform.nix_paths()[1];
+        let terragrunt = TERRAGRUNT_PATHS[0];
+        let mut nixpkgs = Fixture::default();
+        let old = [("a", "1.3.2", "0.50.0"), ("b", "1.3.7", "0.40.0")];
+        for (sha, version, terragrunt_version) in old {
+            nixpkgs.commit(
+                sha,
+                "2023-01-01",
+                &[
+                    (legacy, &package("terraform", version)),
+                    (terragrunt, &package("terragrunt", terragrunt_version)),
+                ],
+            );
+        }
+        // Moved to pkgs/by-name
+        nixpkgs.commit("c", "2024-01-01", &[(by_name, &package("terraform", "1.5.0"))]);
+        nixpkgs.branch("nixpkgs-unstable", "c");
+        let none = Requirements::default();
+
+        let highest = find(&nixpkgs, "~> 1.3.0", &none, Strategy::Highest).unwrap();
+        assert_eq!(highest, ("1.3.7".to_string(), "b".to_string()));
+        let lowest = find(&nixpkgs, "~> 1.3.0", &none, Strategy::Lowest).unwrap();
+        assert_eq!(lowest, ("1.3.2".to_string(), "a".to_string()));
+
+        // Commits whose terragrunt doesn't match are passed over
+        let wanted = VersionConstraint::parse(">= 0.45").unwrap();
+        let requirements = Requirements {
+            terragrunt: Some(&wanted),
+            ..Default::default()
+        };
+        let with_terragrunt = find(&nixpkgs, "~> 1.3.0", &requirements, Strategy::Highest).unwrap();
+        assert_eq!(with_terragrunt, ("1.3.2".to_string(), "a".to_string()));
+
+        assert!(find(&nixpkgs, "~> 1.4.0", &none, Strategy::Highest).is_err());
+    }
+
+    #[test]
+    fn test_tier2_stops_once_nothing_older_can_win() {
+        let path = Engine::Terraform.nix_paths()[0];
+        let mut nixpkgs = Fixture::default();
+        let versions = ["1.2.0", "1.3.0", "1.4.0", "1.5.0", "1.5.7", "1.6.0", "1.7.0"];
+        for (i, version) in versions.iter().enumerate() {
+            let contents = package("terraform", version);
+            nixpkgs.commit(&format!("e{}", i), "2024-01-01", &[(path, &contents)]);
+        }
+        nixpkgs.branch("nixpkgs-unstable", "e6");
+        let none = Requirements::default();
+        let find = |strategy| {
+            let found = find(&nixpkgs, "< 1.6", &none, strategy).unwrap();
+            (found.1, nixpkgs.lookups.swap(0, Ordering::Relaxed))
+        };
+
+        // The unstable head, then history down to the first match
+        assert_eq!(find(Strategy::Highest), ("e4".to_string(), 4));
+        // Down to 1.3.0, which nothing older gets closer to than
+        let closest = Strategy::Closest(Version::parse("1.3.5").unwrap());
+        assert_eq!(find(closest), ("e1".to_string(), 7));
+        // Without a lower bound, the lowest is at the end of history
+        assert_eq!(find(Strategy::Lowest), ("e0".to_string(), 8));
+    }
+
+    #[test]
+    fn test_tier2_walk_limits() {
+        let by_name = Engine::Terraform.nix_paths()[0];
+        let legacy = Engine::Terraform.nix_paths()[1];
+        let mut nixpkgs = Fixture::default();
+        let history = [
+            ("e1", "2019-01-01", legacy, "0.12.0"),
+            ("e2", "2020-01-01", legacy, "0.13.0"),
+            ("e3", "2021-01-01", legacy, "1.0.0"),
+            ("e4", "2022-01-01", by_name, "1.2.0"),
+            ("e5", "2023-01-01", by_name, "1.5.0"),
+        ];
+        for (sha, date, path, version) in history {
+            nixpkgs.commit(sha, date, &[(path, &package("terraform", version))]);
+        }
+        let unchanged = package("terraform", "1.5.0");
+        nixpkgs.commit("u", "2023-06-01", &[(by_name, &unchanged), ("README.md", "")]);
+        nixpkgs.branch("nixpkgs-unstable", "u");
+
+        let walk = |history: HistoryLimit| {
+            let options = SearchOptions { history, jobs: 3 };
+            let paths = Engine::Terraform.nix_paths();
+            let mut shas = Vec::new();
+            let date = |c: &Commit| c.date.clone();
+            walk_history(&nixpkgs, paths, &options, false, date, |c, date| {
+                assert_eq!(c.date, date);
+                shas.push(c.sha.clone());
+                Ok(true)
+            })
+            .unwrap();
+            shas
+        };
+        // Across pages, and on to the old path once the new one's history runs out
+        assert_eq!(walk(HistoryLimit::default()), ["e5", "e4", "e3", "e2", "e1"]);
+        let depth = HistoryLimit {
+            depth: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(walk(depth), ["e5", "e4", "e3"]);
+        let since = HistoryLimit {
+            since: Some("2020-06-01".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(walk(since), ["e5", "e4", "e3"]);
+
+        // The walk stops at the first version below the constraint
+        let found = find(&nixpkgs, "~> 1.0.0", &Requirements::default(), Strategy::Lowest);
+        assert_eq!(found.unwrap(), ("1.0.0".to_string(), "e3".to_string()));
+        assert!(nixpkgs.reads.lock().unwrap().contains(&"e2".to_string()));
+        assert!(!nixpkgs.reads.lock().unwrap().contains(&"e1".to_string()));
+        // Looking up several commits at once finds the same one
+        let options = SearchOptions {
+            jobs: 4,
+            ..Default::default()
+        };
+        let constraint = VersionConstraint::parse("~> 1.0.0").unwrap();
+        let requirements = Requirements::default();
+        let found = find_engine_commit(
+            &constraint,
+            Engine::Terraform,
+            &requirements,
+            &Strategy::Lowest,
+            &options,
+            &nixpkgs,
+            false,
+        );
+        assert_eq!(found.unwrap().commit, "e3");
+    }
+
+    #[test]
+    fn test_bisect_release_branch() {
+        let path = Engine::Terraform.nix_paths()[0];
+        let mut nixpkgs = Fixture::default();
+        nixpkgs.commit("m1", "2023-01-01", &[(path, &package("terraform", "1.4.0"))]);
+        nixpkgs.commit("m2", "2023-05-01", &[(path, &package("terraform", "1.5.0"))]);
+        nixpkgs.branch("nixos-23.05", "m2");
+        let backports = [
+            ("b1", "1.5.1"),
+            ("b2", "1.5.1"),
+            ("b3", "1.5.3"),
+            ("b4", "1.5.5"),
+            ("b5", "1.5.5"),
+        ];
+        for (sha, version) in backports {
+            let terraform = package("terraform", version);
+            nixpkgs.commit_on("nixos-23.05", sha, "2023-06-01", &[(path, &terraform)]);
+        }
+        nixpkgs.commit("m3", "2023-08-01", &[(path, &package("terraform", "1.6.0"))]);
+        nixpkgs.branch("nixpkgs-unstable", "m3");
+        let none = Requirements::default();
+
+        // The newest commit on the branch that the constraint still allows,
+        // rather than the master commit tier 2 would find
+        let found = find(&nixpkgs, "~> 1.5.0, < 1.5.4", &none, Strategy::Highest).unwrap();
+        assert_eq!(found, ("1.5.3".to_string(), "b3".to_string()));
+        // Two branch heads, the two commits on the first page of the branch,
+        // then master's history, which unstable's 1.6.0 might hold a 1.5.3 in,
+        // down to 1.5.0
+        assert_eq!(nixpkgs.lookups.swap(0, Ordering::Relaxed), 6);
+        // The last commit before the bump past the constraint, not the one
+        // that brought the version to the branch
+        let found = find(&nixpkgs, "~> 1.5.1, < 1.5.3", &none, Strategy::Highest).unwrap();
+        assert_eq!(found, ("1.5.1".to_string(), "b2".to_string()));
+
+        // Nothing from the branch's own commits matches, so the fork point wins
+        let found = find(&nixpkgs, "~> 1.5.0, < 1.5.1", &none, Strategy::Highest).unwrap();
+        assert_eq!(found, ("1.5.0".to_string(), "m2".to_string()));
+        // Verbose output copes with refs shorter than a shortened SHA
+        let constraint = VersionConstraint::parse("~> 1.5.0, < 1.5.4").unwrap();
+        let options = SearchOptions::default();
+        let strategy = Strategy::Highest;
+        let verbose = find_engine_commit(
+            &constraint,
+            Engine::Terraform,
+            &none,
+            &strategy,
+            &options,
+            &nixpkgs,
+            true,
+        );
+        assert_eq!(verbose.unwrap().commit, "b3");
+    }
+
+    #[test]
+    fn test_bisect_moved_package() {
+        let by_name = Engine::Terraform.nix_paths()[0];
+        let legacy = Engine::Terraform.nix_paths()[1];
+        let mut nixpkgs = Fixture::default();
+        nixpkgs.commit("m1", "2023-01-01", &[(legacy, &package("terraform", "1.4.0"))]);
+        nixpkgs.commit("m2", "2023-05-01", &[(legacy, &package("terraform", "1.5.0"))]);
+        nixpkgs.branch("nixos-23.05", "m2");
+        let backports = [
+            ("b1", legacy, "1.5.1"),
+            // Moved to pkgs/by-name on the branch
+            ("b2", by_name, "1.5.1"),
+            ("b3", by_name, "1.5.5"),
+        ];
+        for (sha, path, version) in backports {
+            let terraform = package("terraform", version);
+            nixpkgs.commit_on("nixos-23.05", sha, "2023-06-01", &[(path, &terraform)]);
+        }
+        let bisect = |constraint: &str| {
+            let allowed = VersionConstraint::parse(constraint).unwrap().to_set();
+            let last = bisect_branch(&nixpkgs, Engine::Terraform, &allowed, "b3").unwrap();
+            last.map(|(sha, version)| (sha, version.to_string()))
+        };
+
+        // Still at the new path
+        assert_eq!(bisect("~> 1.5.1, < 1.5.5"), Some(("b2".to_string(), "1.5.1".to_string())));
+        // Past the move, back at the old path
+        assert_eq!(bisect("~> 1.5.0, < 1.5.1"), Some(("m2".to_string(), "1.5.0".to_string())));
+        assert_eq!(bisect("< 1.4.0"), None);
+    }
+
+    #[test]
+    fn test_offline_answers_from_cache() {
+        let dir = TestDir::new("offline");
+        let sha = "0123456789abcdef0123456789abcdef01234567";
+        let cache = Cache::open(&dir.join("nixpkgs.json"));
+        cache.set_version(sha, Engine::Terraform.nix_paths()[0], Some("1.5.7".to_string()));
+        // However old, cached responses are all there is offline
+        let response = |value| cache::Response {
+            etag: None,
+            fetched: 0,
+            value,
+        };
+        cache.set_response(
+            "https://api.github.com/repos/NixOS/nixpkgs/commits/nixpkgs-unstable",
+            response(serde_json::json!(sha)),
+        );
+        cache.set_response(
+            "https://api.github.com/repos/NixOS/nixpkgs/git/matching-refs/heads/nixos-",
+            response(serde_json::json!([])),
+        );
+        // History read before, except for one commit in the middle
+        let (uncached, old) = ("f".repeat(40), "a".repeat(40));
+        let commit = |sha: &str| serde_json::json!({"sha": sha, "date": "2023-01-01T00:00:00Z"});
+        cache.set_response(
+            &format!(
+                "https://api.github.com/repos/NixOS/nixpkgs/commits?path={}&sha=master&per_page=100&page=1",
+                Engine::Terraform.nix_paths()[0]
+            ),
+            response(serde_json::json!({
+                "commits": [commit(sha), commit(&uncached), commit(&old)],
+                "more": false,
+            })),
+        );
+        let path = Engine::Terraform.nix_paths()[0];
+        cache.set_version(&old, path, Some("1.4.6".to_string()));
+        cache.set_providers(sha, Some(Vec::new()));
+        let github = GitHub::new(None, Some(cache), true, false).unwrap();
+
+        let none = Requirements::default();
+        let found = find(&github, "~> 1.5.0", &none, Strategy::Highest);
+        let older = find(&github, "~> 1.4.0", &none, Strategy::Highest);
+        let version = engine_version_at_commit(Engine::Terraform, &uncached, &github);
+        let providers = providers_at_commit(sha, &github);
+        let no_providers = providers_at_commit(&old, &github);
+        drop(github);
+
+        assert_eq!(found.unwrap(), ("1.5.7".to_string(), sha.to_string()));
+        // Uncached history is passed over
+        assert_eq!(older.unwrap(), ("1.4.6".to_string(), old.clone()));
+        assert!(version.unwrap_err().to_string().contains("not cached"));
+        assert!(providers.unwrap().is_empty());
+        assert!(is_not_cached(&no_providers.unwrap_err()));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        use reqwest::header::{HeaderMap, HeaderValue};
+        use reqwest::StatusCode;
+        let headers = |pairs: &[(&'static str, &str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(*name, HeaderValue::from_str(value).unwrap());
+            }
+            headers
+        };
+        let now = 1_700_000_000;
+        let exhausted = headers(&[
+            ("x-ratelimit-limit", "60"),
+            ("x-ratelimit-remaining", "0"),
+            ("x-ratelimit-reset", "1700000900"),
+        ]);
+
+        let limited = rate_limited(StatusCode::FORBIDDEN, &exhausted, now).unwrap();
+        assert_eq!(limited, RateLimited { limit: Some(60), until: 1_700_000_900 });
+        assert!(limited.to_string().starts_with(
+            "GitHub rate limited, 0 of 60 requests left, resets at 22:28:20 UTC;"
+        ));
+        let secondary = headers(&[("retry-after", "30"), ("x-ratelimit-remaining", "12")]);
+        assert_eq!(
+            rate_limited(StatusCode::TOO_MANY_REQUESTS, &secondary, now),
+            Some(RateLimited { limit: None, until: now + 30 })
+        );
+        // A 403 with requests left is a plain refusal, and a 200 never counts
+        let left = headers(&[("x-ratelimit-remaining", "59")]);
+        assert_eq!(rate_limited(StatusCode::FORBIDDEN, &left, now), None);
+        assert_eq!(rate_limited(StatusCode::OK, &exhausted, now), None);
+        assert!(is_rate_limit(&anyhow::Error::from(limited).context("listing branches")));
+
+        for attempt in 0..8 {
+            let delay = backoff(attempt);
+            let full = Duration::from_secs(1 << attempt.min(5)).min(Duration::from_secs(30));
+            assert!(delay <= full && delay >= full / 2, "{:?} at {}", delay, attempt);
+        }
+    }
+
+    #[test]
+    fn test_find_commit_in_local_clone() {
+        let dir = TestDir::new("nixpkgs");
+        let package = |v: &str| {
+            format!("{{\n  pname = \"terraform\";\n  version = \"{}\";\n}}\n", v)
+        };
+        let path = Engine::Terraform.nix_paths()[0];
+        let shas = crate::git_repo::fixture_repo(
+            &dir,
+            &[
+                ("master", path, &package("1.5.7")),
+                ("master", path, &package("1.9.0")),
+                ("nixpkgs-unstable", path, &package("1.9.1")),
+                ("nixos-24.05", path, &package("1.8.0")),
+            ],
+        );
+        let source = GitRepo::open(&dir).unwrap();
+        let find = |constraint: &str| {
+            let constraint = VersionConstraint::parse(constraint).unwrap();
+            let requirements = Requirements::default();
+            find_engine_commit(
+                &constraint,
+                Engine::Terraform,
+                &requirements,
+                &Strategy::Highest,
+                &SearchOptions::default(),
+                &source,
+                false,
+            )
+        };
+
+        // Tier 1 finds branch heads, tier 2 walks master's history
+        let unstable = find(">= 1.9.1").unwrap();
+        let stable = find("~> 1.8.0").unwrap();
+        let old = find("~> 1.5.0").unwrap();
+        let missing = find("~> 1.2.0");
+
+        assert_eq!((unstable.version, unstable.commit), (Version::new(1, 9, 1), shas[2].clone()));
+        assert_eq!((stable.version, stable.commit), (Version::new(1, 8, 0), shas[3].clone()));
+        assert_eq!((old.version, old.commit), (Version::new(1, 5, 7), shas[0].clone()));
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_parse_providers_json() {
+        let providers = parse_providers_json(
+            r#"{
+  "aws": {
+    "homepage": "https://registry.terraform.io/providers/hashicorp/aws",
+    "provider-source-address": "registry.terraform.io/hashicorp/aws",
+    "version": "5.31.0"
+  },
+  "cloudflare": {
+    "homepage": "https://registry.terraform.io/providers/cloudflare/cloudflare",
+    "version": "4.20.0"
+  },
+  "broken": {"version": "1.0.0"}
+}"#,
+        )
+        .unwrap();
+
+        assert_eq!(providers.len(), 2);
+        assert_eq!(providers[0].attr, "aws");
+        assert_eq!(providers[0].source, "hashicorp/aws");
+        assert_eq!(providers[1].source, "cloudflare/cloudflare");
+        assert_eq!(providers[1].version, Version::new(4, 20, 0));
+    }
 }
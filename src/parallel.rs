@@ -0,0 +1,149 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::sync::{mpsc, Condvar, Mutex};
+
+/// Run `lookup` on `items` on up to `jobs` threads, handing each result to
+/// `visit` in the order of `items` until it returns `false` or an error.
+///
+/// At most `jobs` lookups run ahead of `visit`, and none are started once it
+/// has stopped, so lookups that would be thrown away are never made. With one
+/// job the lookups happen strictly one after another. Returns whether every
+/// item was visited.
+pub fn for_each_ordered<T: Sync, R: Send>(
+    items: &[T],
+    jobs: usize,
+    lookup: impl Fn(&T) -> R + Sync,
+    mut visit: impl FnMut(&T, R) -> Result<bool>,
+) -> Result<bool> {
+    let gate = Gate {
+        state: Mutex::new(GateState::default()),
+        moved: Condvar::new(),
+    };
+    let jobs = jobs.max(1);
+    let (tx, rx) = mpsc::channel();
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.min(items.len()) {
+            let tx = tx.clone();
+            let (gate, lookup) = (&gate, &lookup);
+            scope.spawn(move || {
+                while let Some(i) = gate.take(items.len(), jobs) {
+                    if tx.send((i, lookup(&items[i]))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        let mut done = BTreeMap::new();
+        let mut visited = 0;
+        let result = 'results: {
+            for (i, result) in &rx {
+                done.insert(i, result);
+                while let Some(result) = done.remove(&visited) {
+                    match visit(&items[visited], result) {
+                        Ok(true) => {}
+                        stop => break 'results stop,
+                    }
+                    visited += 1;
+                    gate.advance(visited);
+                }
+            }
+            Ok(true)
+        };
+        gate.stop();
+        result
+    })
+}
+
+/// Hands out item indices to workers, keeping them within `jobs` of the
+/// items visited so far.
+struct Gate {
+    state: Mutex<GateState>,
+    moved: Condvar,
+}
+
+#[derive(Default)]
+struct GateState {
+    next: usize,
+    visited: usize,
+    stopped: bool,
+}
+
+impl Gate {
+    /// The next item to look up, waiting while the lookups are `jobs` ahead.
+    /// `None` once there are no more, or the visits have stopped.
+    fn take(&self, len: usize, jobs: usize) -> Option<usize> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.stopped || state.next >= len {
+                return None;
+            }
+            if state.next < state.visited + jobs {
+                state.next += 1;
+                return Some(state.next - 1);
+            }
+            state = self.moved.wait(state).unwrap();
+        }
+    }
+
+    fn advance(&self, visited: usize) {
+        self.state.lock().unwrap().visited = visited;
+        self.moved.notify_all();
+    }
+
+    fn stop(&self) {
+        self.state.lock().unwrap().stopped = true;
+        self.moved.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn test_ordered_and_stops_early() {
+        let items: Vec<u64> = (0..50).collect();
+        let looked_up = AtomicUsize::new(0);
+        // Later items finish first, and still come back in order
+        let lookup = |&i: &u64| {
+            looked_up.fetch_add(1, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(10 - i % 10));
+            i * 2
+        };
+
+        let mut seen = Vec::new();
+        let all = for_each_ordered(&items, 4, lookup, |&i, doubled| {
+            assert_eq!(doubled, i * 2);
+            seen.push(i);
+            Ok(i < 20)
+        })
+        .unwrap();
+        assert!(!all);
+        assert_eq!(seen, (0..=20).collect::<Vec<_>>());
+        // No more than `jobs` lookups ran past the last visited item
+        assert!(looked_up.load(Ordering::SeqCst) <= 21 + 4);
+
+        looked_up.store(0, Ordering::SeqCst);
+        let mut seen = Vec::new();
+        let all = for_each_ordered(&items, 1, lookup, |&i, _| {
+            seen.push(i);
+            Ok(true)
+        });
+        assert!(all.unwrap());
+        assert_eq!(seen, items);
+
+        let failed = for_each_ordered(&items, 1, lookup, |&i, _| {
+            if i == 3 {
+                anyhow::bail!("failed at {}", i);
+            }
+            Ok(true)
+        });
+        assert_eq!(failed.unwrap_err().to_string(), "failed at 3");
+        assert_eq!(looked_up.load(Ordering::SeqCst), 50 + 4);
+    }
+}
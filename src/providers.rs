@@ -0,0 +1,85 @@
+use crate::hcl::ProviderRequirement;
+use crate::nixpkgs::PackagedProvider;
+
+/// The outcome of matching `required_providers` against a nixpkgs snapshot.
+#[derive(Debug, Clone, Default)]
+pub struct Resolution {
+    /// Attribute names to pass to `terraform.withPlugins`.
+    pub plugins: Vec<String>,
+    /// Human-readable descriptions of providers that nixpkgs can't supply.
+    pub unmatched: Vec<String>,
+}
+
+/// Pick the nixpkgs package for each required provider, checking that its
+/// version satisfies the configuration's constraint.
+pub fn resolve(required: &[ProviderRequirement], packaged: &[PackagedProvider]) -> Resolution {
+    let mut resolution = Resolution::default();
+    for req in required {
+        let wanted = match req.constraint {
+            Some(ref c) => format!("{} {}", req.source, c),
+            None => req.source.clone(),
+        };
+        match packaged.iter().find(|p| p.source == req.source) {
+            None => resolution
+                .unmatched
+                .push(format!("{} (not packaged in nixpkgs)", wanted)),
+            Some(p) if req.constraint.as_ref().is_some_and(|c| !c.matches(&p.version)) => {
+                resolution
+                    .unmatched
+                    .push(format!("{} (nixpkgs has {})", wanted, p.version))
+            }
+            Some(p) => {
+                if !resolution.plugins.contains(&p.attr) {
+                    resolution.plugins.push(p.attr.clone());
+                }
+            }
+        }
+    }
+    resolution
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constraint::{Version, VersionConstraint};
+
+    fn packaged(attr: &str, source: &str, version: &str) -> PackagedProvider {
+        PackagedProvider {
+            attr: attr.to_string(),
+            source: source.to_string(),
+            version: Version::parse(version).unwrap(),
+        }
+    }
+
+    fn required(source: &str, constraint: Option<&str>) -> ProviderRequirement {
+        ProviderRequirement {
+            source: source.to_string(),
+            constraint: constraint.map(|c| VersionConstraint::parse(c).unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let packaged = [
+            packaged("aws", "hashicorp/aws", "5.31.0"),
+            packaged("cloudflare", "cloudflare/cloudflare", "4.20.0"),
+            packaged("random", "hashicorp/random", "3.6.0"),
+        ];
+        let required = [
+            required("hashicorp/aws", Some("~> 5.0")),
+            required("hashicorp/random", None),
+            required("cloudflare/cloudflare", Some("~> 3.0")),
+            required("example/internal", None),
+        ];
+        let resolution = resolve(&required, &packaged);
+
+        assert_eq!(resolution.plugins, vec!["aws", "random"]);
+        assert_eq!(
+            resolution.unmatched,
+            vec![
+                "cloudflare/cloudflare ~> 3.0 (nixpkgs has 4.20.0)",
+                "example/internal (not packaged in nixpkgs)",
+            ]
+        );
+    }
+}
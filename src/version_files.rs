@@ -0,0 +1,228 @@
+use crate::constraint::{Strategy, VersionConstraint};
+use crate::engine::Engine;
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use std::path::Path;
+
+/// A file where a version manager pins Terraform or OpenTofu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionFile {
+    /// `.terraform-version`, read by tfenv, or tofuenv's `.opentofu-version`.
+    TerraformVersion,
+    /// `.tool-versions`, read by asdf (and mise).
+    ToolVersions,
+    /// `mise.toml`, or `.mise.toml`.
+    Mise,
+}
+
+/// What a version file asks for.
+#[derive(Debug)]
+pub struct Pin {
+    /// The file it was read from, for messages.
+    pub file: String,
+    /// `None` if the file defers to `required_version`, e.g. tfenv's `min-required`.
+    pub constraint: Option<VersionConstraint>,
+    /// The pick the file implies among versions allowed by the constraint.
+    pub strategy: Option<Strategy>,
+}
+
+/// Read the pin for `engine` from `file` in `dir`, if the file exists and has one.
+pub fn read(dir: &Path, file: VersionFile, engine: Engine) -> Result<Option<Pin>> {
+    let names: &[&str] = match (file, engine) {
+        (VersionFile::TerraformVersion, Engine::Terraform) => &[".terraform-version"],
+        (VersionFile::TerraformVersion, Engine::OpenTofu) => &[".opentofu-version"],
+        (VersionFile::ToolVersions, _) => &[".tool-versions"],
+        (VersionFile::Mise, _) => &["mise.toml", ".mise.toml"],
+    };
+    let path = match names.iter().map(|n| dir.join(n)).find(|p| p.is_file()) {
+        Some(p) => p,
+        None => return Ok(None),
+    };
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+
+    let spec = match file {
+        VersionFile::TerraformVersion => content
+            .lines()
+            .map(|l| l.trim())
+            .find(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(str::to_string),
+        VersionFile::ToolVersions => tool_versions_entry(&content, engine.package()),
+        VersionFile::Mise => mise_entry(&content, engine.package()),
+    };
+    let spec = match spec {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+
+    let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+    let (constraint, strategy) = parse_spec(&spec)
+        .with_context(|| format!("unsupported {} version \"{}\" in {}", engine, spec, file_name))?;
+    Ok(Some(Pin {
+        constraint: constraint.map(|c| c.with_source(&file_name)),
+        file: file_name,
+        strategy,
+    }))
+}
+
+/// The first version of the `tool` line in a `.tool-versions` file.
+/// Later versions on the line are fallbacks, which tfg doesn't need.
+fn tool_versions_entry(content: &str, tool: &str) -> Option<String> {
+    content.lines().find_map(|line| {
+        let line = line.split('#').next().unwrap_or("");
+        let mut fields = line.split_whitespace();
+        if fields.next() == Some(tool) {
+            fields.next().map(str::to_string)
+        } else {
+            None
+        }
+    })
+}
+
+/// The version of `tool` in a `mise.toml`: `terraform = "1.5"` under `[tools]`,
+/// a list of versions (the first wins), `{ version = "1.5" }`, or the dotted
+/// `tools.terraform` key.
+///
+/// This reads only the simple one-line forms mise's docs use, rather than
+/// parsing TOML in full.
+fn mise_entry(content: &str, tool: &str) -> Option<String> {
+    let re = Regex::new(&format!(
+        r#"^(?:tools\.)?"?{}"?\s*=\s*(?:\[\s*|\{{\s*version\s*=\s*)?["']([^"']+)["']"#,
+        regex::escape(tool)
+    ))
+    .unwrap();
+
+    let mut table = String::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('[') && !line.starts_with("[[") {
+            table = line.trim_matches(['[', ']']).trim().to_string();
+            continue;
+        }
+        let dotted = table.is_empty() && line.starts_with("tools.");
+        if table != "tools" && !dotted {
+            continue;
+        }
+        if let Some(c) = re.captures(line) {
+            return Some(c[1].to_string());
+        }
+    }
+    None
+}
+
+/// Turn a version manager's spec into a constraint and the pick it implies.
+///
+/// Besides exact versions this understands `latest`, prefixes such as `1.5`
+/// (mise and asdf's `latest:1.5`), tfenv's `latest:<regex>` as long as the
+/// regex only anchors a version prefix, and tfenv's `min-required` and
+/// `latest-allowed`, which defer to `required_version`.
+fn parse_spec(spec: &str) -> Result<(Option<VersionConstraint>, Option<Strategy>)> {
+    let spec = spec.trim().trim_start_matches('v');
+    match spec {
+        "min-required" => return Ok((None, Some(Strategy::Lowest))),
+        "latest-allowed" | "latest" => return Ok((None, Some(Strategy::Highest))),
+        _ => {}
+    }
+
+    if let Some(pattern) = spec.strip_prefix("latest:") {
+        let (prefix, exact) = regex_prefix(pattern)
+            .context("only version prefixes such as ^1\\.5 are supported")?;
+        return Ok((Some(prefix_constraint(&prefix, exact)?), Some(Strategy::Highest)));
+    }
+
+    // A full version is exact; mise treats anything shorter as a prefix
+    if spec.matches('.').count() >= 2 {
+        return Ok((Some(VersionConstraint::parse(&format!("= {}", spec))?), None));
+    }
+    Ok((Some(prefix_constraint(spec, false)?), Some(Strategy::Highest)))
+}
+
+/// The version prefix a `latest:` regex such as `^1\.5` or `^1.5.7$` selects,
+/// and whether it is anchored at the end too.
+fn regex_prefix(pattern: &str) -> Option<(String, bool)> {
+    let pattern = pattern.strip_prefix('^').unwrap_or(pattern);
+    let (pattern, exact) = match pattern.strip_suffix('$') {
+        Some(p) => (p, true),
+        None => (pattern.strip_suffix(".*").unwrap_or(pattern), false),
+    };
+    let prefix = pattern.replace("\\.", ".");
+    let prefix = prefix.trim_end_matches('.');
+    let re = Regex::new(r"^\d+(\.\d+){0,2}$").unwrap();
+    re.is_match(prefix).then(|| (prefix.to_string(), exact))
+}
+
+/// The versions starting with `prefix`, e.g. `1.5` for `>= 1.5.0, < 1.6.0`.
+fn prefix_constraint(prefix: &str, exact: bool) -> Result<VersionConstraint> {
+    let parts: Vec<u64> = prefix
+        .split('.')
+        .map(|p| p.parse::<u64>())
+        .collect::<std::result::Result<_, _>>()
+        .context("not a version")?;
+    let text = match (parts.as_slice(), exact) {
+        ([major, minor, patch], _) => format!("= {}.{}.{}", major, minor, patch),
+        ([major, minor], false) => format!(">= {}.{}.0, < {}.{}.0", major, minor, major, minor + 1),
+        ([major], false) => format!(">= {}.0.0, < {}.0.0", major, major + 1),
+        (_, true) => format!("= {}", prefix),
+        _ => bail!("not a version"),
+    };
+    VersionConstraint::parse(&text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constraint::Version;
+
+    fn allows(spec: &str, version: &str) -> bool {
+        let (constraint, _) = parse_spec(spec).unwrap();
+        constraint.unwrap().matches(&Version::parse(version).unwrap())
+    }
+
+    #[test]
+    fn test_parse_spec() {
+        assert!(allows("1.5.7", "1.5.7"));
+        assert!(!allows("1.5.7", "1.5.8"));
+        assert!(allows("v1.5.7", "1.5.7"));
+        assert!(allows("1.5", "1.5.9"));
+        assert!(!allows("1.5", "1.6.0"));
+        assert!(allows("1", "1.9.0"));
+        assert!(!allows("1", "2.0.0"));
+        assert!(allows("latest:^1\\.5", "1.5.3"));
+        assert!(!allows("latest:^1\\.5", "1.50.0"));
+        assert!(allows("latest:^1.4.6$", "1.4.6"));
+
+        assert!(matches!(parse_spec("min-required"), Ok((None, Some(Strategy::Lowest)))));
+        assert!(matches!(parse_spec("latest"), Ok((None, Some(Strategy::Highest)))));
+        assert!(parse_spec("latest:(alpha|beta)").is_err());
+        assert!(parse_spec("ref:main").is_err());
+    }
+
+    #[test]
+    fn test_entries() {
+        let tool_versions = "nodejs 20.1.0\nterraform 1.5.7 1.4.6 # fallback\n";
+        assert_eq!(tool_versions_entry(tool_versions, "terraform").as_deref(), Some("1.5.7"));
+        assert_eq!(tool_versions_entry(tool_versions, "opentofu"), None);
+        assert_eq!(tool_versions_entry("nodejs 20.1.0\n", "terraform"), None);
+
+        let mise = "[env]\nterraform = \"not this\"\n\n[tools]\nnode = \"20\"\nterraform = \"1.5\"\n";
+        assert_eq!(mise_entry(mise, "terraform").as_deref(), Some("1.5"));
+        assert_eq!(
+            mise_entry("[tools]\nterraform = [\"1.6.2\", \"1.5\"]\n", "terraform").as_deref(),
+            Some("1.6.2")
+        );
+        assert_eq!(
+            mise_entry("[tools]\nterraform = { version = \"1.7\", os = [\"linux\"] }\n", "terraform")
+                .as_deref(),
+            Some("1.7")
+        );
+        assert_eq!(
+            mise_entry("tools.terraform = \"1.8.0\"\n", "terraform").as_deref(),
+            Some("1.8.0")
+        );
+        assert_eq!(mise_entry("[tools]\nopentofu = \"1.6\"\n", "terraform"), None);
+        assert_eq!(
+            mise_entry("[tools]\nopentofu = \"1.6\"\n", "opentofu").as_deref(),
+            Some("1.6")
+        );
+    }
+}
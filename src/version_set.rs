@@ -0,0 +1,273 @@
+use crate::constraint::Version;
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A point on the version line between versions, used as an interval endpoint.
+///
+/// `Below(v)` sits immediately before `v` and `Above(v)` immediately after it,
+/// so every bound can be expressed as a half-open `[lower, upper)` interval of
+/// cuts without needing a "next version" operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cut {
+    BelowAll,
+    Below(Version),
+    Above(Version),
+    AboveAll,
+}
+
+impl Ord for Cut {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Cut::BelowAll, Cut::BelowAll) | (Cut::AboveAll, Cut::AboveAll) => Ordering::Equal,
+            (Cut::BelowAll, _) | (_, Cut::AboveAll) => Ordering::Less,
+            (_, Cut::BelowAll) | (Cut::AboveAll, _) => Ordering::Greater,
+            (Cut::Below(a), Cut::Below(b)) | (Cut::Above(a), Cut::Above(b)) => a.cmp(b),
+            (Cut::Below(a), Cut::Above(b)) => {
+                if a <= b {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            }
+            (Cut::Above(a), Cut::Below(b)) => {
+                if a < b {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            }
+        }
+    }
+}
+
+impl PartialOrd for Cut {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The versions between two cuts. Always non-empty (`lower < upper`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interval {
+    pub lower: Cut,
+    pub upper: Cut,
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let (Cut::Below(a), Cut::Above(b)) = (&self.lower, &self.upper) {
+            if a == b {
+                return write!(f, "{{{}}}", a);
+            }
+        }
+        match self.lower {
+            Cut::BelowAll => write!(f, "(-inf")?,
+            Cut::Below(ref v) => write!(f, "[{}", v)?,
+            Cut::Above(ref v) => write!(f, "({}", v)?,
+            Cut::AboveAll => unreachable!("interval cannot start above all versions"),
+        }
+        match self.upper {
+            Cut::BelowAll => unreachable!("interval cannot end below all versions"),
+            Cut::Below(ref v) => write!(f, ", {})", v),
+            Cut::Above(ref v) => write!(f, ", {}]", v),
+            Cut::AboveAll => write!(f, ", +inf)"),
+        }
+    }
+}
+
+/// A set of versions as a normalized union of intervals: sorted, disjoint and
+/// with no two intervals touching.
+///
+/// The set describes a range on the version line. Terraform's additional rule
+/// that pre-releases only match comparators naming them is applied by
+/// `VersionConstraint`, which keeps releases and pre-releases in separate sets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionSet {
+    intervals: Vec<Interval>,
+}
+
+impl VersionSet {
+    pub fn full() -> Self {
+        Self::range(Cut::BelowAll, Cut::AboveAll)
+    }
+
+    pub fn empty() -> Self {
+        VersionSet {
+            intervals: Vec::new(),
+        }
+    }
+
+    /// The versions between `lower` and `upper`; empty if they are out of order.
+    pub fn range(lower: Cut, upper: Cut) -> Self {
+        Self::from_intervals(vec![Interval { lower, upper }])
+    }
+
+    /// Exactly one version.
+    pub fn point(v: &Version) -> Self {
+        Self::range(Cut::Below(v.clone()), Cut::Above(v.clone()))
+    }
+
+    fn from_intervals(mut intervals: Vec<Interval>) -> Self {
+        intervals.retain(|i| i.lower < i.upper);
+        intervals.sort_by(|a, b| a.lower.cmp(&b.lower));
+
+        let mut merged: Vec<Interval> = Vec::with_capacity(intervals.len());
+        for interval in intervals {
+            match merged.last_mut() {
+                Some(last) if interval.lower <= last.upper => {
+                    if interval.upper > last.upper {
+                        last.upper = interval.upper;
+                    }
+                }
+                _ => merged.push(interval),
+            }
+        }
+        VersionSet { intervals: merged }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    pub fn union(&self, other: &VersionSet) -> VersionSet {
+        let mut intervals = self.intervals.clone();
+        intervals.extend(other.intervals.iter().cloned());
+        Self::from_intervals(intervals)
+    }
+
+    pub fn intersect(&self, other: &VersionSet) -> VersionSet {
+        let mut intervals = Vec::new();
+        for a in &self.intervals {
+            for b in &other.intervals {
+                let lower = a.lower.clone().max(b.lower.clone());
+                let upper = a.upper.clone().min(b.upper.clone());
+                intervals.push(Interval { lower, upper });
+            }
+        }
+        Self::from_intervals(intervals)
+    }
+
+    pub fn complement(&self) -> VersionSet {
+        let mut intervals = Vec::new();
+        let mut lower = Cut::BelowAll;
+        for interval in &self.intervals {
+            intervals.push(Interval {
+                lower,
+                upper: interval.lower.clone(),
+            });
+            lower = interval.upper.clone();
+        }
+        intervals.push(Interval {
+            lower,
+            upper: Cut::AboveAll,
+        });
+        Self::from_intervals(intervals)
+    }
+
+    pub fn is_subset(&self, other: &VersionSet) -> bool {
+        self.intersect(&other.complement()).is_empty()
+    }
+
+    /// Whether every version in the set is lower than `v`.
+    pub fn is_below(&self, v: &Version) -> bool {
+        match self.intervals.last() {
+            Some(last) => last.upper <= Cut::Below(v.clone()),
+            None => true,
+        }
+    }
+
+    /// Whether every version in the set is higher than `v`.
+    pub fn is_above(&self, v: &Version) -> bool {
+        match self.intervals.first() {
+            Some(first) => Cut::Above(v.clone()) <= first.lower,
+            None => true,
+        }
+    }
+}
+
+impl fmt::Display for VersionSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.intervals.is_empty() {
+            return f.write_str("{}");
+        }
+        let parts: Vec<String> = self.intervals.iter().map(|i| i.to_string()).collect();
+        f.write_str(&parts.join(" | "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> Version {
+        Version::parse(s).unwrap()
+    }
+
+    fn gte(s: &str) -> VersionSet {
+        VersionSet::range(Cut::Below(v(s)), Cut::AboveAll)
+    }
+
+    fn lt(s: &str) -> VersionSet {
+        VersionSet::range(Cut::BelowAll, Cut::Below(v(s)))
+    }
+
+    #[test]
+    fn test_cut_ordering() {
+        assert!(Cut::Below(v("1.5.0")) < Cut::Above(v("1.5.0")));
+        assert!(Cut::Above(v("1.5.0")) < Cut::Below(v("1.5.1")));
+        assert!(Cut::BelowAll < Cut::Below(v("0.0.0")));
+        assert!(Cut::Above(v("99.0.0")) < Cut::AboveAll);
+    }
+
+    #[test]
+    fn test_intersect_and_union() {
+        let a = gte("1.3.0").intersect(&lt("2.0.0"));
+        assert_eq!(a.to_string(), "[1.3.0, 2.0.0)");
+
+        let b = gte("1.5.0").intersect(&lt("1.6.0"));
+        assert_eq!(a.intersect(&b), b);
+        assert!(gte("2.0.0").intersect(&lt("2.0.0")).is_empty());
+
+        // Touching intervals merge into one
+        let joined = lt("1.5.0").union(&gte("1.5.0"));
+        assert_eq!(joined, VersionSet::full());
+        let split = lt("1.5.0").union(&gte("1.6.0"));
+        assert_eq!(split.to_string(), "(-inf, 1.5.0) | [1.6.0, +inf)");
+    }
+
+    #[test]
+    fn test_complement_and_subset() {
+        let point = VersionSet::point(&v("1.5.0"));
+        assert_eq!(point.to_string(), "{1.5.0}");
+        assert_eq!(point.complement().to_string(), "(-inf, 1.5.0) | (1.5.0, +inf)");
+        assert_eq!(point.complement().complement(), point);
+        assert!(VersionSet::full().complement().is_empty());
+        assert_eq!(VersionSet::full().complement().complement(), VersionSet::full());
+
+        let narrow = gte("1.5.0").intersect(&lt("1.6.0"));
+        let wide = gte("1.3.0");
+        assert!(narrow.is_subset(&wide));
+        assert!(!wide.is_subset(&narrow));
+        assert!(VersionSet::full().complement().is_subset(&narrow));
+    }
+
+    #[test]
+    fn test_is_above() {
+        let set = gte("1.5.0").intersect(&lt("1.6.0")).union(&gte("2.0.0"));
+        assert!(set.is_above(&v("1.4.9")));
+        assert!(!set.is_above(&v("1.5.0")));
+        assert!(!set.is_above(&v("1.7.0")));
+        let open = VersionSet::range(Cut::Above(v("1.5.0")), Cut::AboveAll);
+        assert!(open.is_above(&v("1.5.0")));
+        assert!(!VersionSet::full().is_above(&v("0.0.0")));
+    }
+
+    #[test]
+    fn test_is_below() {
+        let set = lt("1.5.0").union(&VersionSet::point(&v("1.6.0")));
+        assert!(set.is_below(&v("1.6.1")));
+        assert!(!set.is_below(&v("1.6.0")));
+        assert!(!set.is_below(&v("1.5.5")));
+        assert!(!VersionSet::full().is_below(&v("99.0.0")));
+    }
+}